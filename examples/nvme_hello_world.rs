@@ -1,9 +1,10 @@
-#![feature(maybe_uninit)]
 extern crate libc;
+extern crate spdk_rs;
 extern crate spdk_sys;
 #[macro_use]
 extern crate lazy_static;
 
+use spdk_rs::env::{Env, EnvOpts};
 use spdk_sys::*;
 use std::cell::Cell;
 use std::ffi::CString;
@@ -67,7 +68,7 @@ struct hello_world_sequence {
 unsafe fn register_ns(ctrlr: *mut spdk_nvme_ctrlr, ns: *mut spdk_nvme_ns) {
     let mut entry: *mut ns_entry = ptr::null_mut();
     let cdata: *const spdk_nvme_ctrlr_data = spdk_nvme_ctrlr_get_data(ctrlr);
-    entry = mem::MaybeUninit::uninitialized().as_mut_ptr();
+    entry = Box::into_raw(Box::new(mem::zeroed()));
     if entry.is_null() {
         panic!();
     }
@@ -177,7 +178,7 @@ unsafe extern "C" fn attach_cb(
     let mut entry: *mut ctrlr_entry = ptr::null_mut();
     let mut ns: *mut spdk_nvme_ns = ptr::null_mut();
     let cdata: *const spdk_nvme_ctrlr_data = spdk_nvme_ctrlr_get_data(ctrlr);
-    entry = mem::MaybeUninit::uninitialized().as_mut_ptr();
+    entry = Box::into_raw(Box::new(mem::zeroed()));
     if entry.is_null() {
         panic!();
     }
@@ -362,23 +363,22 @@ unsafe fn cleanup() {
 }
 
 fn main() {
-    let opt: *mut spdk_env_opts = &mut Default::default();
-    unsafe {
-        /*
-         * SPDK relies on an abstraction around the local environment
-         * named env that handles memory allocation and PCI device operations.
-         * This library must be initialized first.
-         *
-         */
-        spdk_env_opts_init(opt);
-        (*opt).name = CString::new("hello_world")
-            .expect("CString::new failed")
-            .as_ptr();
-        (*opt).shm_id = 0;
-        if (spdk_env_init(opt) < 0) {
-            println!("Initializing NVMe Controllers\n");
+    /*
+     * SPDK relies on an abstraction around the local environment
+     * named env that handles memory allocation and PCI device operations.
+     * This library must be initialized first.
+     *
+     */
+    let mut opts = EnvOpts::new();
+    opts.name("hello_world").shm_id(0);
+    let _env = match Env::init(opts) {
+        Ok(env) => env,
+        Err(e) => {
+            println!("Unable to initialize SPDK env: {}", e);
             process::exit(1);
         }
+    };
+    unsafe {
         println!("Initializing NVMe Controllers\n");
         /*
          * Start the SPDK NVMe enumeration process.  probe_cb will be called
@@ -1,4 +1,3 @@
-#![feature(maybe_uninit)]
 extern crate libc;
 extern crate spdk_sys;
 #[macro_use]
@@ -67,7 +66,7 @@ struct hello_world_sequence {
 unsafe fn register_ns(ctrlr: *mut spdk_nvme_ctrlr, ns: *mut spdk_nvme_ns) {
     let mut entry: *mut ns_entry = ptr::null_mut();
     let cdata: *const spdk_nvme_ctrlr_data = spdk_nvme_ctrlr_get_data(ctrlr);
-    entry = mem::MaybeUninit::uninitialized().as_mut_ptr();
+    entry = Box::into_raw(Box::new(mem::zeroed()));
     if entry.is_null() {
         panic!();
     }
@@ -177,7 +176,7 @@ unsafe extern "C" fn attach_cb(
     let mut entry: *mut ctrlr_entry = ptr::null_mut();
     let mut ns: *mut spdk_nvme_ns = ptr::null_mut();
     let cdata: *const spdk_nvme_ctrlr_data = spdk_nvme_ctrlr_get_data(ctrlr);
-    entry = mem::MaybeUninit::uninitialized().as_mut_ptr();
+    entry = Box::into_raw(Box::new(mem::zeroed()));
     if entry.is_null() {
         panic!();
     }
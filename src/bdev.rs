@@ -2,6 +2,11 @@
 /// For example, spdk_bdev_open() is implemented in the context instead
 /// because spdk_bdev_open works with struct spdk_bdev* and
 /// struct spdk_bdev_desc**, which usually used with the context struct.
+use crate::env;
+use crate::json;
+use crate::thread;
+use crate::util;
+use spdk;
 use std::ffi::{c_void, CStr, CString};
 use std::marker;
 use std::ptr;
@@ -10,6 +15,7 @@ use failure::Error;
 use futures::channel::mpsc;
 use futures::channel::oneshot;
 use futures::channel::oneshot::Sender;
+use serde_json::Value;
 
 #[derive(Debug, Fail)]
 pub enum BdevError {
@@ -25,6 +31,12 @@ pub enum BdevError {
     )]
     ReadError(String, i32, u64, u64),
 
+    #[fail(
+        display = "Error in flush completion({}): {}, offset: {}, length: {}",
+        _0, _1, _2, _3
+    )]
+    FlushError(String, i32, u64, u64),
+
     #[fail(display = "Could not find a bdev: {}", _0)]
     NotFound(String),
 
@@ -37,7 +49,7 @@ pub enum BdevError {
 
 #[derive(Clone)]
 pub struct SpdkBdev {
-    raw: *mut raw::spdk_bdev,
+    raw: *mut spdk::spdk_bdev,
 }
 
 /// spdk_bdev_get_by_name()
@@ -47,7 +59,7 @@ where
 {
     let name_cstring = CString::new(bdev_name.clone().into()).expect("Couldn't create a string");
 
-    let bdev = unsafe { raw::spdk_bdev_get_by_name(name_cstring.as_ptr()) };
+    let bdev = unsafe { spdk::spdk_bdev_get_by_name(name_cstring.as_ptr()) };
     if bdev.is_null() {
         return Err(BdevError::NotFound(bdev_name.clone().into()))?;
     }
@@ -58,7 +70,7 @@ where
 /// spdk_bdev_open()
 pub fn open(bdev: SpdkBdev, write: bool, bdev_desc: &mut SpdkBdevDesc) -> Result<(), Error> {
     unsafe {
-        let rc = raw::spdk_bdev_open(
+        let rc = spdk::spdk_bdev_open(
             bdev.to_raw(),
             write,
             None,
@@ -74,13 +86,13 @@ pub fn open(bdev: SpdkBdev, write: bool, bdev_desc: &mut SpdkBdevDesc) -> Result
 
 /// spdk_bdev_close()
 pub fn close(desc: SpdkBdevDesc) {
-    unsafe { raw::spdk_bdev_close(desc.to_raw()) }
+    unsafe { spdk::spdk_bdev_close(desc.to_raw()) }
 }
 
 /// spdk_bdev_first()
 pub fn first() -> Option<SpdkBdev> {
     unsafe {
-        let ptr = raw::spdk_bdev_first();
+        let ptr = spdk::spdk_bdev_first();
         if ptr.is_null() {
             None
         } else {
@@ -92,7 +104,7 @@ pub fn first() -> Option<SpdkBdev> {
 /// spdk_bdev_next()
 pub fn next(prev: &SpdkBdev) -> Option<SpdkBdev> {
     unsafe {
-        let ptr = raw::spdk_bdev_next(prev.raw);
+        let ptr = spdk::spdk_bdev_next(prev.raw);
         if ptr.is_null() {
             None
         } else {
@@ -103,7 +115,7 @@ pub fn next(prev: &SpdkBdev) -> Option<SpdkBdev> {
 
 pub fn get_io_channel(desc: SpdkBdevDesc) -> Result<thread::SpdkIoChannel, Error> {
     unsafe {
-        let ptr = raw::spdk_bdev_get_io_channel(desc.to_raw());
+        let ptr = spdk::spdk_bdev_get_io_channel(desc.to_raw());
         if ptr.is_null() {
             Err(BdevError::IOChannelError())?
         } else {
@@ -114,12 +126,12 @@ pub fn get_io_channel(desc: SpdkBdevDesc) -> Result<thread::SpdkIoChannel, Error
 
 /// spdk_bdev_get_block_size()
 pub fn get_block_size(bdev: SpdkBdev) -> u32 {
-    unsafe { raw::spdk_bdev_get_block_size(bdev.to_raw()) }
+    unsafe { spdk::spdk_bdev_get_block_size(bdev.to_raw()) }
 }
 
 /// spdk_bdev_get_buf_align()
 pub fn get_buf_align(bdev: SpdkBdev) -> usize {
-    unsafe { raw::spdk_bdev_get_buf_align(bdev.to_raw()) }
+    unsafe { spdk::spdk_bdev_get_buf_align(bdev.to_raw()) }
 }
 
 /// spdk_bdev_write()
@@ -131,32 +143,35 @@ pub async fn write<'a>(
     nbytes: u64,
 ) -> Result<(), Error> {
     let (sender, receiver) = oneshot::channel();
-    let ret: i32;
-    unsafe {
-        ret = raw::spdk_bdev_write(
+    let sender_ptr = cb_arg::<()>(sender);
+    let ret = unsafe {
+        spdk::spdk_bdev_write(
             desc.raw,
             ch.to_raw(),
             buf.to_raw(),
             offset,
             nbytes,
             Some(spdk_bdev_io_completion_cb),
-            cb_arg::<()>(sender),
-        );
+            sender_ptr,
+        )
+    };
+    let res = match check_submitted(ret, sender_ptr) {
+        Ok(()) => receiver.await.expect("Cancellation is not supported"),
+        Err(ret) => Err(ret),
     };
-    // TODO: we probably need to handle the case where ret != 0
-    let res = await!(receiver).expect("Cancellation is not supported");
 
     match res {
         Ok(()) => Ok(()),
-        Err(_e) => Err(BdevError::WriteError(
+        Err(ret) => Err(BdevError::WriteError(
             desc.spdk_bdev_desc_get_bdev().name().to_string(),
-            -1,
+            ret,
             offset,
             nbytes,
         ))?,
     }
 }
 
+/// spdk_bdev_read()
 pub async fn read<'a>(
     desc: SpdkBdevDesc,
     ch: &'a thread::SpdkIoChannel,
@@ -165,35 +180,85 @@ pub async fn read<'a>(
     nbytes: u64,
 ) -> Result<(), Error> {
     let (sender, receiver) = oneshot::channel();
-    let ret: i32;
-    unsafe {
-        ret = raw::spdk_bdev_read(
+    let sender_ptr = cb_arg::<()>(sender);
+    let ret = unsafe {
+        spdk::spdk_bdev_read(
             desc.raw,
             ch.to_raw(),
             buf.to_raw(),
             offset,
             nbytes,
             Some(spdk_bdev_io_completion_cb),
-            cb_arg::<()>(sender),
-        );
+            sender_ptr,
+        )
+    };
+    let res = match check_submitted(ret, sender_ptr) {
+        Ok(()) => receiver.await.expect("Cancellation is not supported"),
+        Err(ret) => Err(ret),
     };
 
-    // TODO: we probably need to handle the case where ret != 0
-    let res = await!(receiver).expect("Cancellation is not supported");
+    match res {
+        Ok(()) => Ok(()),
+        Err(ret) => Err(BdevError::ReadError(
+            desc.spdk_bdev_desc_get_bdev().name().to_string(),
+            ret,
+            offset,
+            nbytes,
+        ))?,
+    }
+}
+
+/// spdk_bdev_flush()
+pub async fn flush<'a>(
+    desc: SpdkBdevDesc,
+    ch: &'a thread::SpdkIoChannel,
+    offset: u64,
+    nbytes: u64,
+) -> Result<(), Error> {
+    let (sender, receiver) = oneshot::channel();
+    let sender_ptr = cb_arg::<()>(sender);
+    let ret = unsafe {
+        spdk::spdk_bdev_flush(
+            desc.raw,
+            ch.to_raw(),
+            offset,
+            nbytes,
+            Some(spdk_bdev_io_completion_cb),
+            sender_ptr,
+        )
+    };
+    let res = match check_submitted(ret, sender_ptr) {
+        Ok(()) => receiver.await.expect("Cancellation is not supported"),
+        Err(ret) => Err(ret),
+    };
 
     match res {
         Ok(()) => Ok(()),
-        Err(_e) => Err(BdevError::ReadError(
+        Err(ret) => Err(BdevError::FlushError(
             desc.spdk_bdev_desc_get_bdev().name().to_string(),
-            -1,
+            ret,
             offset,
             nbytes,
         ))?,
     }
 }
 
+/// spdk_bdev_config_text()
+///
+/// Legacy INI configuration of every bdev module.
+pub fn config_text() -> Result<String, Error> {
+    util::with_memstream(|fp| unsafe { spdk::spdk_bdev_config_text(fp) })
+}
+
+/// spdk_bdev_subsystem_config_json()
+///
+/// RPC calls that recreate the current bdevs, as written by `save_config`.
+pub fn config_json() -> Result<Value, Error> {
+    json::write_json_value(|w| unsafe { spdk::spdk_bdev_subsystem_config_json(w) })
+}
+
 impl SpdkBdev {
-    pub fn from_raw(raw: *mut raw::spdk_bdev) -> SpdkBdev {
+    pub fn from_raw(raw: *mut spdk::spdk_bdev) -> SpdkBdev {
         unsafe { SpdkBdev { raw: raw } }
     }
 
@@ -207,14 +272,14 @@ impl SpdkBdev {
         str_slice
     }
 
-    pub fn to_raw(&self) -> *mut raw::spdk_bdev {
+    pub fn to_raw(&self) -> *mut spdk::spdk_bdev {
         self.raw
     }
 }
 
 #[derive(Clone)]
 pub struct SpdkBdevDesc {
-    raw: *mut raw::spdk_bdev_desc,
+    raw: *mut spdk::spdk_bdev_desc,
 }
 
 impl SpdkBdevDesc {
@@ -224,22 +289,22 @@ impl SpdkBdevDesc {
         }
     }
 
-    pub fn from_raw(raw: *mut raw::spdk_bdev_desc) -> SpdkBdevDesc {
+    pub fn from_raw(raw: *mut spdk::spdk_bdev_desc) -> SpdkBdevDesc {
         unsafe { SpdkBdevDesc { raw: raw } }
     }
 
-    pub fn to_raw(&self) -> *mut raw::spdk_bdev_desc {
+    pub fn to_raw(&self) -> *mut spdk::spdk_bdev_desc {
         self.raw
     }
 
-    pub fn mut_to_raw(&mut self) -> *mut *mut raw::spdk_bdev_desc {
+    pub fn mut_to_raw(&mut self) -> *mut *mut spdk::spdk_bdev_desc {
         &mut self.raw
     }
 
     pub fn spdk_bdev_desc_get_bdev(&self) -> SpdkBdev {
         let ptr;
         unsafe {
-            ptr = raw::spdk_bdev_desc_get_bdev(self.raw);
+            ptr = spdk::spdk_bdev_desc_get_bdev(self.raw);
         }
         SpdkBdev { raw: ptr }
     }
@@ -249,12 +314,25 @@ fn cb_arg<T>(sender: Sender<Result<T, i32>>) -> *mut c_void {
     Box::into_raw(Box::new(sender)) as *const _ as *mut c_void
 }
 
+/// Takes back the argument given to `spdk_bdev_io_completion_cb` if the
+/// I/O could not be submitted, in which case the callback never runs.
+fn check_submitted(ret: i32, sender_ptr: *mut c_void) -> Result<(), i32> {
+    if ret != 0 {
+        drop(unsafe { Box::from_raw(sender_ptr as *mut Sender<Result<(), i32>>) });
+        return Err(ret);
+    }
+    Ok(())
+}
+
 extern "C" fn spdk_bdev_io_completion_cb(
-    bdev_io: *mut raw::spdk_bdev_io,
+    bdev_io: *mut spdk::spdk_bdev_io,
     success: bool,
     sender_ptr: *mut c_void,
 ) {
+    unsafe { spdk::spdk_bdev_free_io(bdev_io) };
     let sender = unsafe { Box::from_raw(sender_ptr as *mut Sender<Result<(), i32>>) };
-    let ret = if !success { Err(-1) } else { Ok(()) };
-    sender.send(ret).expect("Receiver is gone");
+    // The completion only tells whether the I/O succeeded.
+    let ret = if !success { Err(-libc::EIO) } else { Ok(()) };
+    // The future may have been dropped in the meantime.
+    let _ = sender.send(ret);
 }
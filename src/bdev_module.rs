@@ -2,7 +2,7 @@ use spdk;
 use std::ptr;
 
 pub struct SpdkBdevIO {
-    raw: *mut raw::spdk_bdev_io,
+    raw: *mut spdk::spdk_bdev_io,
 }
 
 impl SpdkBdevIO {
@@ -0,0 +1,827 @@
+//! Legacy INI-style configuration files, as read by `spdk_conf_read()` and
+//! passed to the app with `SpdkAppOpts::config_file`.
+//!
+//! Values are always looked up through spdk_conf, so they are split and
+//! unquoted exactly like the C code does. spdk_conf has no way to enumerate
+//! the items of a section though, so their keys are taken from the text of
+//! the file, read along with it.
+//!
+//! `Section::deserialize` is stricter than the C getters: numbers must parse
+//! in full and booleans must be one of the spellings the C code knows,
+//! anything else is an error instead of a default.
+
+use spdk;
+
+use std::error::Error as StdError;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::fs;
+use std::os::raw::c_char;
+use std::path::Path;
+use std::str::FromStr;
+
+use failure::Error;
+use serde::de::{self, DeserializeOwned, DeserializeSeed, MapAccess, SeqAccess, Visitor};
+
+#[derive(Debug, Fail)]
+pub enum ConfError {
+    #[fail(display = "Could not read config file {}: {}", _0, _1)]
+    ReadError(String, i32),
+
+    #[fail(display = "Section [{}] not found", _0)]
+    SectionNotFound(String),
+
+    #[fail(display = "Could not write config file {}: {}", _0, _1)]
+    WriteError(String, String),
+}
+
+/// Error deserializing a section, see `Conf::deserialize_section`.
+#[derive(Debug)]
+pub struct DeserializeError(String);
+
+impl fmt::Display for DeserializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid config: {}", self.0)
+    }
+}
+
+impl StdError for DeserializeError {}
+
+impl de::Error for DeserializeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        DeserializeError(msg.to_string())
+    }
+}
+
+/// Owned `spdk_conf`.
+pub struct Conf {
+    raw: *mut spdk::spdk_conf,
+    /// Text of the file, to enumerate keys.
+    text: String,
+}
+
+impl Conf {
+    /// spdk_conf_allocate()
+    ///
+    /// Creates an empty configuration.
+    pub fn new() -> Conf {
+        let raw = unsafe { spdk::spdk_conf_allocate() };
+        assert!(!raw.is_null(), "Failed to allocate conf");
+        Conf {
+            raw,
+            text: String::new(),
+        }
+    }
+
+    /// spdk_conf_read()
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Conf, Error> {
+        let path = path.as_ref().to_string_lossy().into_owned();
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) => return Err(ConfError::ReadError(path, e.raw_os_error().unwrap_or(-1)))?,
+        };
+        let path_cstring = CString::new(path.clone()).expect("Couldn't create a string");
+
+        let mut conf = Conf::new();
+        let rc = unsafe { spdk::spdk_conf_read(conf.raw, path_cstring.as_ptr()) };
+        if rc != 0 {
+            return Err(ConfError::ReadError(path, rc))?;
+        }
+        conf.text = text;
+        Ok(conf)
+    }
+
+    pub fn to_raw(&self) -> *mut spdk::spdk_conf {
+        self.raw
+    }
+
+    /// spdk_conf_find_section()
+    pub fn section(&self, name: &str) -> Option<Section<'_>> {
+        let name_cstring = CString::new(name).expect("Couldn't create a string");
+        let raw = unsafe { spdk::spdk_conf_find_section(self.raw, name_cstring.as_ptr()) };
+        Section::from_raw(raw, self)
+    }
+
+    /// spdk_conf_first_section() / spdk_conf_next_section()
+    pub fn sections(&self) -> Sections<'_> {
+        Sections {
+            next: Section::from_raw(unsafe { spdk::spdk_conf_first_section(self.raw) }, self),
+        }
+    }
+
+    /// Sections whose name starts with `prefix`, e.g. `Nvme` for `[Nvme0]`, `[Nvme1]`...
+    pub fn sections_with_prefix<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> impl Iterator<Item = Section<'a>> {
+        self.sections()
+            .filter(move |section| section.match_prefix(prefix))
+    }
+
+    /// Deserializes the section `name` into `T`, see `Section::deserialize`.
+    pub fn deserialize_section<T: DeserializeOwned>(&self, name: &str) -> Result<T, Error> {
+        match self.section(name) {
+            Some(section) => section.deserialize(),
+            None => Err(ConfError::SectionNotFound(name.to_string()))?,
+        }
+    }
+}
+
+impl Drop for Conf {
+    /// spdk_conf_free()
+    fn drop(&mut self) {
+        unsafe { spdk::spdk_conf_free(self.raw) }
+    }
+}
+
+impl Default for Conf {
+    fn default() -> Conf {
+        Conf::new()
+    }
+}
+
+/// Iterator over the sections of a `Conf`, in file order.
+pub struct Sections<'a> {
+    next: Option<Section<'a>>,
+}
+
+impl<'a> Iterator for Sections<'a> {
+    type Item = Section<'a>;
+
+    fn next(&mut self) -> Option<Section<'a>> {
+        let section = self.next.take()?;
+        self.next = Section::from_raw(
+            unsafe { spdk::spdk_conf_next_section(section.raw) },
+            section.conf,
+        );
+        Some(section)
+    }
+}
+
+/// One occurrence of a key in a section, with its whitespace separated values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Item {
+    pub key: String,
+    pub values: Vec<String>,
+}
+
+/// `spdk_conf_section`, borrowed from its `Conf`.
+#[derive(Clone, Copy)]
+pub struct Section<'a> {
+    raw: *mut spdk::spdk_conf_section,
+    conf: &'a Conf,
+}
+
+impl<'a> Section<'a> {
+    fn from_raw(raw: *mut spdk::spdk_conf_section, conf: &'a Conf) -> Option<Section<'a>> {
+        if raw.is_null() {
+            None
+        } else {
+            Some(Section { raw, conf })
+        }
+    }
+
+    pub fn to_raw(&self) -> *mut spdk::spdk_conf_section {
+        self.raw
+    }
+
+    /// spdk_conf_section_get_name()
+    pub fn name(&self) -> String {
+        to_string(unsafe { spdk::spdk_conf_section_get_name(self.raw) }).unwrap_or_default()
+    }
+
+    /// spdk_conf_section_get_num()
+    ///
+    /// The number at the end of the name, e.g. 1 for `[Nvme1]`.
+    pub fn num(&self) -> i32 {
+        unsafe { spdk::spdk_conf_section_get_num(self.raw) }
+    }
+
+    /// spdk_conf_section_match_prefix()
+    pub fn match_prefix(&self, prefix: &str) -> bool {
+        let prefix_cstring = CString::new(prefix).expect("Couldn't create a string");
+        unsafe { spdk::spdk_conf_section_match_prefix(self.raw, prefix_cstring.as_ptr()) }
+    }
+
+    /// spdk_conf_section_get_val()
+    ///
+    /// First value of the first occurrence of `key`.
+    pub fn value(&self, key: &str) -> Option<String> {
+        let key_cstring = CString::new(key).expect("Couldn't create a string");
+        to_string(unsafe { spdk::spdk_conf_section_get_val(self.raw, key_cstring.as_ptr()) })
+    }
+
+    /// spdk_conf_section_get_nval()
+    ///
+    /// First value of the `idx`th occurrence of `key`.
+    pub fn nth_value(&self, key: &str, idx: usize) -> Option<String> {
+        let key_cstring = CString::new(key).expect("Couldn't create a string");
+        to_string(unsafe {
+            spdk::spdk_conf_section_get_nval(self.raw, key_cstring.as_ptr(), idx as i32)
+        })
+    }
+
+    /// spdk_conf_section_get_nmval()
+    ///
+    /// `idx2`th value of the `idx1`th occurrence of `key`.
+    pub fn nm_value(&self, key: &str, idx1: usize, idx2: usize) -> Option<String> {
+        let key_cstring = CString::new(key).expect("Couldn't create a string");
+        to_string(unsafe {
+            spdk::spdk_conf_section_get_nmval(
+                self.raw,
+                key_cstring.as_ptr(),
+                idx1 as i32,
+                idx2 as i32,
+            )
+        })
+    }
+
+    /// spdk_conf_section_get_intval()
+    ///
+    /// `None` if `key` is missing. Like the C function, the value is not
+    /// checked: text that is not a number comes back as whatever
+    /// spdk_conf_section_get_intval() makes of it. `deserialize` rejects it.
+    pub fn int_value(&self, key: &str) -> Option<i32> {
+        self.value(key)?;
+        let key_cstring = CString::new(key).expect("Couldn't create a string");
+        Some(unsafe { spdk::spdk_conf_section_get_intval(self.raw, key_cstring.as_ptr()) })
+    }
+
+    /// spdk_conf_section_get_boolval()
+    ///
+    /// `default` if `key` is missing or is not one of the spellings listed
+    /// in `parse_bool`.
+    pub fn bool_value(&self, key: &str, default: bool) -> bool {
+        let key_cstring = CString::new(key).expect("Couldn't create a string");
+        unsafe { spdk::spdk_conf_section_get_boolval(self.raw, key_cstring.as_ptr(), default) }
+    }
+
+    /// Iterates over the first value of every occurrence of `key`.
+    pub fn values<'b>(&'b self, key: &'b str) -> impl Iterator<Item = String> + 'b {
+        (0..)
+            .map(move |idx| self.nth_value(key, idx))
+            .take_while(Option::is_some)
+            .map(Option::unwrap)
+    }
+
+    /// Iterates over all values of the `idx`th occurrence of `key`.
+    pub fn line<'b>(&'b self, key: &'b str, idx: usize) -> impl Iterator<Item = String> + 'b {
+        (0..)
+            .map(move |idx2| self.nm_value(key, idx, idx2))
+            .take_while(Option::is_some)
+            .map(Option::unwrap)
+    }
+
+    /// Distinct keys of the section, in file order.
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys = Vec::new();
+        for item in self.items() {
+            if !keys.contains(&item.key) {
+                keys.push(item.key);
+            }
+        }
+        keys
+    }
+
+    /// Every item of the section, in file order.
+    pub fn items(&self) -> Vec<Item> {
+        let mut seen: Vec<String> = Vec::new();
+        section_keys(&self.conf.text, &self.name())
+            .into_iter()
+            .map(|key| {
+                let idx = seen.iter().filter(|seen| **seen == key).count();
+                let values = self.line(&key, idx).collect();
+                seen.push(key.clone());
+                Item { key, values }
+            })
+            .collect()
+    }
+
+    /// Deserializes the section into a struct, looking up each of its fields
+    /// as a key. Scalar fields take the first value of the key, sequences
+    /// take one element per occurrence of the key, and tuples take the values
+    /// of a single occurrence. Missing keys are left to serde, so `Option`
+    /// and `#[serde(default)]` fields can be omitted.
+    pub fn deserialize<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        Ok(T::deserialize(SectionDeserializer { section: *self })?)
+    }
+}
+
+fn to_string(ptr: *const c_char) -> Option<String> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(
+            unsafe { CStr::from_ptr(ptr) }
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
+
+/// Keys of the items of section `name` in `text`, including repeated ones.
+///
+/// Follows the line handling of spdk_conf_read(): a trailing `\` joins the
+/// next line, comments are whole lines starting with `#`, and a section that
+/// appears several times gathers the items of every occurrence. Only the
+/// first word of an item is needed, its values are read through spdk_conf.
+fn section_keys(text: &str, name: &str) -> Vec<String> {
+    let mut keys = Vec::new();
+    let mut in_section = false;
+    let mut continued = false;
+    for line in text.lines() {
+        let was_continued = continued;
+        continued = line.trim_end().ends_with('\\');
+        if was_continued {
+            continue;
+        }
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            in_section = match line[1..].find(']') {
+                Some(end) => line[1..=end].trim() == name,
+                None => false,
+            };
+        } else if in_section {
+            if let Some(key) = line.split_whitespace().next() {
+                keys.push(key.to_string());
+            }
+        }
+    }
+    keys
+}
+
+/// Spellings accepted by spdk_conf_section_get_boolval(), whatever the case.
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "yes" | "y" | "true" => Some(true),
+        "no" | "n" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+struct SectionDeserializer<'a> {
+    section: Section<'a>,
+}
+
+impl<'de, 'a> de::Deserializer<'de> for SectionDeserializer<'a> {
+    type Error = DeserializeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, DeserializeError> {
+        Err(de::Error::custom(format!(
+            "section [{}] can only be deserialized into a struct",
+            self.section.name()
+        )))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DeserializeError> {
+        visitor.visit_map(FieldsAccess {
+            section: self.section,
+            fields: fields.iter(),
+            key: None,
+        })
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+/// Yields the fields of the struct that are present in the section.
+struct FieldsAccess<'a> {
+    section: Section<'a>,
+    fields: std::slice::Iter<'static, &'static str>,
+    key: Option<&'static str>,
+}
+
+impl<'de, 'a> MapAccess<'de> for FieldsAccess<'a> {
+    type Error = DeserializeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, DeserializeError> {
+        for &field in self.fields.by_ref() {
+            if self.section.value(field).is_some() {
+                self.key = Some(field);
+                return seed
+                    .deserialize(de::value::StrDeserializer::new(field))
+                    .map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, DeserializeError> {
+        let key = self.key.take().expect("Value requested before its key");
+        seed.deserialize(ValueDeserializer {
+            section: self.section,
+            key,
+            level: Level::Key,
+        })
+    }
+}
+
+/// Which part of the values of a key is being deserialized.
+#[derive(Clone, Copy)]
+enum Level {
+    /// Every occurrence of the key.
+    Key,
+    /// The given occurrence of the key.
+    Line(usize),
+    /// A single value.
+    Word(usize, usize),
+}
+
+struct ValueDeserializer<'a> {
+    section: Section<'a>,
+    key: &'static str,
+    level: Level,
+}
+
+impl<'a> ValueDeserializer<'a> {
+    fn scalar(&self) -> Result<String, DeserializeError> {
+        let (idx1, idx2) = match self.level {
+            Level::Key => (0, 0),
+            Level::Line(idx1) => (idx1, 0),
+            Level::Word(idx1, idx2) => (idx1, idx2),
+        };
+        self.section
+            .nm_value(self.key, idx1, idx2)
+            .ok_or_else(|| de::Error::custom(format!("missing value for {}", self.key)))
+    }
+
+    fn parse<T: FromStr>(&self) -> Result<T, DeserializeError> {
+        let value = self.scalar()?;
+        value
+            .parse()
+            .map_err(|_| de::Error::custom(format!("invalid value for {}: {}", self.key, value)))
+    }
+
+    fn words(&self) -> Result<ValuesAccess<'a>, DeserializeError> {
+        let line = match self.level {
+            Level::Key => 0,
+            Level::Line(line) => line,
+            Level::Word(..) => {
+                return Err(de::Error::custom(format!(
+                    "{} expects a single value",
+                    self.key
+                )))
+            }
+        };
+        Ok(ValuesAccess {
+            section: self.section,
+            key: self.key,
+            line: Some(line),
+            next: 0,
+        })
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeserializeError> {
+                visitor.$visit(self.parse()?)
+            }
+        )*
+    };
+}
+
+impl<'de, 'a> de::Deserializer<'de> for ValueDeserializer<'a> {
+    type Error = DeserializeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeserializeError> {
+        visitor.visit_string(self.scalar()?)
+    }
+
+    /// See `parse_bool`.
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeserializeError> {
+        let value = self.scalar()?;
+        match parse_bool(&value) {
+            Some(value) => visitor.visit_bool(value),
+            None => Err(de::Error::custom(format!(
+                "invalid value for {}: {}",
+                self.key, value
+            ))),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeserializeError> {
+        // Only keys that are present get here.
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, DeserializeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeserializeError> {
+        match self.level {
+            Level::Key => visitor.visit_seq(ValuesAccess {
+                section: self.section,
+                key: self.key,
+                line: None,
+                next: 0,
+            }),
+            _ => visitor.visit_seq(self.words()?),
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, DeserializeError> {
+        visitor.visit_seq(self.words()?)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, DeserializeError> {
+        visitor.visit_seq(self.words()?)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DeserializeError> {
+        visitor.visit_enum(de::value::StringDeserializer::new(self.scalar()?))
+    }
+
+    serde::forward_to_deserialize_any! {
+        i128 u128 str string bytes byte_buf unit unit_struct map struct
+        identifier ignored_any
+    }
+}
+
+/// Occurrences of a key (`line` is `None`) or values of one occurrence.
+struct ValuesAccess<'a> {
+    section: Section<'a>,
+    key: &'static str,
+    line: Option<usize>,
+    next: usize,
+}
+
+impl<'de, 'a> SeqAccess<'de> for ValuesAccess<'a> {
+    type Error = DeserializeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, DeserializeError> {
+        let (level, present) = match self.line {
+            None => (
+                Level::Line(self.next),
+                self.section.nth_value(self.key, self.next).is_some(),
+            ),
+            Some(line) => (
+                Level::Word(line, self.next),
+                self.section.nm_value(self.key, line, self.next).is_some(),
+            ),
+        };
+        if !present {
+            return Ok(None);
+        }
+        self.next += 1;
+        seed.deserialize(ValueDeserializer {
+            section: self.section,
+            key: self.key,
+            level,
+        })
+        .map(Some)
+    }
+}
+
+/// Builds config text in the format read by `Conf::read`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConfWriter {
+    text: String,
+}
+
+impl ConfWriter {
+    pub fn new() -> ConfWriter {
+        ConfWriter::default()
+    }
+
+    /// Starts a new section, the following items belong to it.
+    pub fn section(&mut self, name: &str) -> &mut Self {
+        if !self.text.is_empty() {
+            self.text.push('\n');
+        }
+        self.text.push_str(&format!("[{}]\n", name));
+        self
+    }
+
+    /// Adds an item with the given whitespace separated values.
+    pub fn item<I, V>(&mut self, key: &str, values: I) -> &mut Self
+    where
+        I: IntoIterator<Item = V>,
+        V: fmt::Display,
+    {
+        self.text.push_str("  ");
+        self.text.push_str(key);
+        for value in values {
+            self.text.push_str(&format!(" {}", value));
+        }
+        self.text.push('\n');
+        self
+    }
+
+    pub fn comment(&mut self, comment: &str) -> &mut Self {
+        for line in comment.lines() {
+            self.text.push_str(&format!("# {}\n", line));
+        }
+        self
+    }
+
+    pub fn write_to<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        match fs::write(path.as_ref(), &self.text) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(ConfError::WriteError(
+                path.as_ref().to_string_lossy().into_owned(),
+                e.to_string(),
+            ))?,
+        }
+    }
+}
+
+impl fmt::Display for ConfWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use hamcrest2::prelude::*;
+    use serde::Deserialize;
+    use std::env;
+    use std::process;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Malloc {
+        #[serde(rename = "NumberOfLuns")]
+        number_of_luns: u32,
+        #[serde(rename = "LunSizeInMB")]
+        lun_size_in_mb: u64,
+        #[serde(rename = "Split", default)]
+        split: Vec<(String, u32)>,
+        #[serde(rename = "Enable")]
+        enable: bool,
+        #[serde(rename = "Missing")]
+        missing: Option<String>,
+    }
+
+    fn write_conf(name: &str, writer: &ConfWriter) -> Conf {
+        // Unique across concurrent test runs.
+        let path = env::temp_dir().join(format!("spdk-rs-{}-{}.conf", process::id(), name));
+        writer.write_to(&path).unwrap();
+        let conf = Conf::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        conf
+    }
+
+    fn sample() -> ConfWriter {
+        let mut writer = ConfWriter::new();
+        writer
+            .comment("Generated")
+            .section("Malloc")
+            .item("NumberOfLuns", vec![2])
+            .item("LunSizeInMB", vec![64])
+            .item("Split", vec!["Malloc0", "2"])
+            .item("Split", vec!["Malloc1", "4"])
+            .item("Enable", vec!["Yes"])
+            .section("Nvme1")
+            .item(
+                "TransportID",
+                vec!["\"trtype:PCIe traddr:0000:00:04.0\"", "Nvme0"],
+            );
+        writer
+    }
+
+    #[test]
+    fn section_keys_are_read_from_text() {
+        let text = concat!(
+            "[Global]\n",
+            "  A 1 # not a comment\n",
+            "# [Malloc]\n",
+            "\n",
+            "[Malloc]\n",
+            "  B \"1 #\" \\\n",
+            "    2\n",
+            "  C\n",
+            "[Global]\n",
+            "  D\n",
+            "[Malloc]\n",
+            "  B 3\n",
+        );
+
+        assert_that!(
+            section_keys(text, "Malloc"),
+            is(equal_to(vec!["B", "C", "B"]))
+        );
+        assert_that!(section_keys(text, "Global"), is(equal_to(vec!["A", "D"])));
+    }
+
+    #[test]
+    fn booleans_are_spelled_like_in_c() {
+        assert_that!(parse_bool("Yes"), is(equal_to(Some(true))));
+        assert_that!(parse_bool("y"), is(equal_to(Some(true))));
+        assert_that!(parse_bool("TRUE"), is(equal_to(Some(true))));
+        assert_that!(parse_bool("No"), is(equal_to(Some(false))));
+        assert_that!(parse_bool("N"), is(equal_to(Some(false))));
+        assert_that!(parse_bool("false"), is(equal_to(Some(false))));
+        assert_that!(parse_bool("enable"), is(equal_to(None)));
+    }
+
+    #[test]
+    fn written_conf_is_read_back() {
+        let conf = write_conf("read-back", &sample());
+
+        let names: Vec<String> = conf.sections().map(|section| section.name()).collect();
+        assert_that!(names, is(equal_to(vec!["Malloc", "Nvme1"])));
+        assert_that!(conf.sections_with_prefix("Nvme").count(), is(equal_to(1)));
+
+        let malloc = conf.section("Malloc").unwrap();
+        assert_that!(malloc.int_value("NumberOfLuns"), is(equal_to(Some(2))));
+        assert_that!(malloc.bool_value("Enable", false), is(true));
+        assert_that!(
+            malloc.values("Split").collect::<Vec<_>>(),
+            is(equal_to(vec!["Malloc0", "Malloc1"]))
+        );
+        assert_that!(
+            malloc.keys(),
+            is(equal_to(vec![
+                "NumberOfLuns",
+                "LunSizeInMB",
+                "Split",
+                "Enable"
+            ]))
+        );
+        assert_that!(
+            malloc.items()[3].clone(),
+            is(equal_to(Item {
+                key: "Split".to_string(),
+                values: vec!["Malloc1".to_string(), "4".to_string()],
+            }))
+        );
+        assert_that!(conf.section("Nvme1").unwrap().num(), is(equal_to(1)));
+    }
+
+    #[test]
+    fn section_is_deserialized() {
+        let conf = write_conf("deserialize", &sample());
+
+        let malloc: Malloc = conf.deserialize_section("Malloc").unwrap();
+
+        assert_that!(
+            malloc,
+            is(equal_to(Malloc {
+                number_of_luns: 2,
+                lun_size_in_mb: 64,
+                split: vec![("Malloc0".to_string(), 2), ("Malloc1".to_string(), 4)],
+                enable: true,
+                missing: None,
+            }))
+        );
+        assert_that!(conf.deserialize_section::<Malloc>("Nvme1"), is(err()));
+    }
+}
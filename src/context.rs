@@ -198,7 +198,7 @@ impl AppContext {
         let owned_content = CString::new(message).unwrap();
         let content: *const c_char = owned_content.as_ptr();
         unsafe {
-            raw::snprintf(
+            spdk::snprintf(
                 self.buff,
                 spdk::spdk_bdev_get_block_size(self.bdev) as usize,
                 fmt,
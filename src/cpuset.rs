@@ -0,0 +1,197 @@
+use spdk;
+
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::iter::FromIterator;
+use std::str::FromStr;
+
+use failure::Error;
+
+#[derive(Debug, Fail)]
+pub enum CpuSetError {
+    #[fail(display = "Invalid core mask: {}", _0)]
+    ParseError(String),
+}
+
+/// Owned `spdk_cpuset`.
+pub struct CpuSet {
+    raw: *mut spdk::spdk_cpuset,
+}
+
+unsafe impl Send for CpuSet {}
+
+impl CpuSet {
+    /// spdk_cpuset_alloc()
+    ///
+    /// Creates an empty set.
+    pub fn new() -> CpuSet {
+        let raw = unsafe { spdk::spdk_cpuset_alloc() };
+        assert!(!raw.is_null(), "Failed to allocate cpuset");
+        CpuSet { raw }
+    }
+
+    /// spdk_cpuset_parse()
+    ///
+    /// Accepts either a hexadecimal mask (`0x3`) or a list of cores (`[0-1,4]`).
+    pub fn parse(mask: &str) -> Result<CpuSet, Error> {
+        let mask_cstring = match CString::new(mask) {
+            Ok(mask_cstring) => mask_cstring,
+            Err(_) => return Err(CpuSetError::ParseError(mask.to_string()))?,
+        };
+        let set = CpuSet::new();
+        let rc = unsafe { spdk::spdk_cpuset_parse(set.raw, mask_cstring.as_ptr()) };
+        if rc != 0 {
+            return Err(CpuSetError::ParseError(mask.to_string()))?;
+        }
+        Ok(set)
+    }
+
+    pub fn to_raw(&self) -> *mut spdk::spdk_cpuset {
+        self.raw
+    }
+
+    /// spdk_cpuset_zero()
+    pub fn zero(&mut self) {
+        unsafe { spdk::spdk_cpuset_zero(self.raw) }
+    }
+
+    /// spdk_cpuset_set_cpu()
+    pub fn set(&mut self, cpu: u32, state: bool) {
+        assert!(cpu < spdk::SPDK_CPUSET_SIZE, "Core {} out of range", cpu);
+        unsafe { spdk::spdk_cpuset_set_cpu(self.raw, cpu, state) }
+    }
+
+    /// spdk_cpuset_get_cpu()
+    pub fn get(&self, cpu: u32) -> bool {
+        cpu < spdk::SPDK_CPUSET_SIZE && unsafe { spdk::spdk_cpuset_get_cpu(self.raw, cpu) }
+    }
+
+    /// spdk_cpuset_count()
+    pub fn count(&self) -> u32 {
+        unsafe { spdk::spdk_cpuset_count(self.raw) }
+    }
+
+    /// spdk_cpuset_and()
+    pub fn and(&mut self, other: &CpuSet) {
+        unsafe { spdk::spdk_cpuset_and(self.raw, other.raw) }
+    }
+
+    /// spdk_cpuset_or()
+    pub fn or(&mut self, other: &CpuSet) {
+        unsafe { spdk::spdk_cpuset_or(self.raw, other.raw) }
+    }
+
+    /// Iterates over the cores that are part of the set.
+    pub fn cores<'a>(&'a self) -> impl Iterator<Item = u32> + 'a {
+        (0..spdk::SPDK_CPUSET_SIZE).filter(move |cpu| self.get(*cpu))
+    }
+}
+
+impl Drop for CpuSet {
+    fn drop(&mut self) {
+        unsafe { spdk::spdk_cpuset_free(self.raw) }
+    }
+}
+
+impl Default for CpuSet {
+    fn default() -> CpuSet {
+        CpuSet::new()
+    }
+}
+
+impl Clone for CpuSet {
+    /// spdk_cpuset_copy()
+    fn clone(&self) -> CpuSet {
+        let set = CpuSet::new();
+        unsafe { spdk::spdk_cpuset_copy(set.raw, self.raw) };
+        set
+    }
+}
+
+impl PartialEq for CpuSet {
+    /// spdk_cpuset_equal()
+    fn eq(&self, other: &CpuSet) -> bool {
+        unsafe { spdk::spdk_cpuset_equal(self.raw, other.raw) }
+    }
+}
+
+impl Eq for CpuSet {}
+
+impl FromStr for CpuSet {
+    type Err = Error;
+
+    fn from_str(mask: &str) -> Result<CpuSet, Error> {
+        CpuSet::parse(mask)
+    }
+}
+
+impl FromIterator<u32> for CpuSet {
+    fn from_iter<I: IntoIterator<Item = u32>>(cores: I) -> CpuSet {
+        let mut set = CpuSet::new();
+        for core in cores {
+            set.set(core, true);
+        }
+        set
+    }
+}
+
+impl fmt::Display for CpuSet {
+    /// spdk_cpuset_fmt()
+    ///
+    /// Formats the set as a hexadecimal mask, which `CpuSet::parse` accepts back.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mask = unsafe { CStr::from_ptr(spdk::spdk_cpuset_fmt(self.raw)) };
+        write!(f, "0x{}", mask.to_string_lossy())
+    }
+}
+
+impl fmt::Debug for CpuSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CpuSet({})", self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use hamcrest2::prelude::*;
+
+    #[test]
+    fn mask_round_trips() {
+        let set = CpuSet::parse("0x5").unwrap();
+
+        assert_that!(set.count(), is(equal_to(2)));
+        assert_that!(set.cores().collect::<Vec<_>>(), is(equal_to(vec![0, 2])));
+        let formatted = set.to_string();
+        assert_that!(formatted.parse::<CpuSet>().unwrap(), is(equal_to(set)));
+    }
+
+    #[test]
+    fn list_and_mask_are_equal() {
+        let list = CpuSet::parse("[0-1,4]").unwrap();
+        let mask = CpuSet::parse("0x13").unwrap();
+
+        assert_that!(&list, is(equal_to(&mask)));
+        assert_that!(vec![0, 1, 4].into_iter().collect::<CpuSet>(), is(equal_to(mask)));
+    }
+
+    #[test]
+    fn and_or() {
+        let mut and = CpuSet::parse("0x3").unwrap();
+        let mut or = and.clone();
+        let other = CpuSet::parse("0x6").unwrap();
+
+        and.and(&other);
+        or.or(&other);
+
+        assert_that!(and, is(equal_to(CpuSet::parse("0x2").unwrap())));
+        assert_that!(or, is(equal_to(CpuSet::parse("0x7").unwrap())));
+    }
+
+    #[test]
+    fn invalid_mask_is_rejected() {
+        assert_that!(CpuSet::parse("[0-"), is(err()));
+        assert_that!(CpuSet::parse("zz"), is(err()));
+    }
+}
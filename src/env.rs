@@ -1,7 +1,179 @@
+use crate::cpuset::CpuSet;
+use crate::pci::PciAddr;
 use spdk;
 use std::ffi::{c_void, CStr, CString};
 use std::os::raw::{c_char, c_int};
 use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use failure::Error;
+
+#[derive(Debug, Fail)]
+pub enum EnvError {
+    #[fail(display = "Could not launch a thread on core {}: {}", _0, _1)]
+    LaunchError(u32, i32),
+
+    #[fail(display = "The environment is already initialized")]
+    AlreadyInitialized(),
+
+    #[fail(display = "Could not initialize the environment: {}", _0)]
+    InitError(i32),
+
+    #[fail(display = "A PCI whitelist and a PCI blacklist cannot be used together")]
+    PciFilterConflict(),
+}
+
+/// Set by the first successful `Env::init`.
+static ENV_INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// spdk_env_opts
+///
+/// Options of an environment started without the app framework, see
+/// `Env::init`. The options own every string they point to.
+#[derive(Default)]
+pub struct EnvOpts {
+    raw: spdk::spdk_env_opts,
+    name: Option<CString>,
+    core_mask: Option<CString>,
+    hugedir: Option<CString>,
+    pci_whitelist: Vec<spdk::spdk_pci_addr>,
+    pci_blacklist: Vec<spdk::spdk_pci_addr>,
+}
+
+impl EnvOpts {
+    /// spdk_env_opts_init()
+    pub fn new() -> Self {
+        let mut opts = EnvOpts::default();
+        unsafe {
+            spdk::spdk_env_opts_init(&mut opts.raw as *mut spdk::spdk_env_opts);
+        }
+        opts
+    }
+
+    pub fn name(&mut self, name: &str) -> &mut Self {
+        set_string(&mut self.raw.name, &mut self.name, name);
+        self
+    }
+
+    /// Cores the environment may use.
+    pub fn core_mask(&mut self, mask: &CpuSet) -> &mut Self {
+        set_string(
+            &mut self.raw.core_mask,
+            &mut self.core_mask,
+            &mask.to_string(),
+        );
+        self
+    }
+
+    /// Shared memory id, processes using the same id share their memory.
+    pub fn shm_id(&mut self, shm_id: i32) -> &mut Self {
+        self.raw.shm_id = shm_id;
+        self
+    }
+
+    /// Amount of memory to reserve at startup, in MB.
+    pub fn mem_size(&mut self, mem_size: i32) -> &mut Self {
+        self.raw.mem_size = mem_size;
+        self
+    }
+
+    pub fn mem_channel(&mut self, mem_channel: i32) -> &mut Self {
+        self.raw.mem_channel = mem_channel;
+        self
+    }
+
+    pub fn master_core(&mut self, master_core: i32) -> &mut Self {
+        self.raw.master_core = master_core;
+        self
+    }
+
+    /// Back all the memory with a single hugetlbfs file.
+    pub fn hugepage_single_segments(&mut self, single: bool) -> &mut Self {
+        self.raw.hugepage_single_segments = single;
+        self
+    }
+
+    /// Unlink the hugepage files once they are mapped.
+    pub fn unlink_hugepage(&mut self, unlink: bool) -> &mut Self {
+        self.raw.unlink_hugepage = unlink;
+        self
+    }
+
+    /// hugetlbfs mount point to use.
+    pub fn hugedir(&mut self, hugedir: &str) -> &mut Self {
+        set_string(&mut self.raw.hugedir, &mut self.hugedir, hugedir);
+        self
+    }
+
+    /// Do not touch any PCI device.
+    pub fn no_pci(&mut self, no_pci: bool) -> &mut Self {
+        self.raw.no_pci = no_pci;
+        self
+    }
+
+    /// Only use the PCI device at `addr`. Can be called several times.
+    pub fn pci_whitelist(&mut self, addr: PciAddr) -> &mut Self {
+        self.pci_whitelist.push(addr.to_raw());
+        self
+    }
+
+    /// Never use the PCI device at `addr`. Can be called several times.
+    pub fn pci_blacklist(&mut self, addr: PciAddr) -> &mut Self {
+        self.pci_blacklist.push(addr.to_raw());
+        self
+    }
+
+    /// The raw options, pointing into `self`.
+    pub fn to_raw(&mut self) -> *mut spdk::spdk_env_opts {
+        self.raw.num_pci_addr = 0;
+        self.raw.pci_whitelist = ptr::null_mut();
+        self.raw.pci_blacklist = ptr::null_mut();
+        if !self.pci_whitelist.is_empty() {
+            self.raw.num_pci_addr = self.pci_whitelist.len();
+            self.raw.pci_whitelist = self.pci_whitelist.as_mut_ptr();
+        } else if !self.pci_blacklist.is_empty() {
+            self.raw.num_pci_addr = self.pci_blacklist.len();
+            self.raw.pci_blacklist = self.pci_blacklist.as_mut_ptr();
+        }
+        &mut self.raw as *mut spdk::spdk_env_opts
+    }
+}
+
+fn set_string(raw: &mut *const c_char, owned: &mut Option<CString>, value: &str) {
+    let value = CString::new(value).expect("Couldn't create a string");
+    *raw = value.as_ptr();
+    *owned = Some(value);
+}
+
+/// Proof that the environment was initialized by `Env::init`, for tools that
+/// use the NVMe driver or DMA memory without starting the app framework and
+/// its reactors.
+///
+/// SPDK cannot tear the environment down, so it stays up after the guard is
+/// dropped and no other guard can be created for the rest of the process.
+pub struct Env {
+    _opts: EnvOpts,
+}
+
+impl Env {
+    /// spdk_env_init()
+    ///
+    /// Fails if the environment was already initialized by this function.
+    pub fn init(mut opts: EnvOpts) -> Result<Env, Error> {
+        if !opts.pci_whitelist.is_empty() && !opts.pci_blacklist.is_empty() {
+            return Err(EnvError::PciFilterConflict())?;
+        }
+        if ENV_INITIALIZED.swap(true, Ordering::SeqCst) {
+            return Err(EnvError::AlreadyInitialized())?;
+        }
+        let rc = unsafe { spdk::spdk_env_init(opts.to_raw()) };
+        if rc < 0 {
+            ENV_INITIALIZED.store(false, Ordering::SeqCst);
+            return Err(EnvError::InitError(rc))?;
+        }
+        Ok(Env { _opts: opts })
+    }
+}
 
 #[derive(Clone)]
 pub struct Buf {
@@ -45,3 +217,100 @@ pub fn dma_zmalloc(size: usize, align: usize) -> Buf {
     assert!(!ptr.is_null(), "Failed to malloc");
     Buf { raw: ptr }
 }
+
+/// spdk_dma_free()
+pub fn dma_free(buf: Buf) {
+    unsafe { spdk::spdk_dma_free(buf.to_raw()) }
+}
+
+/// spdk_env_get_core_count()
+pub fn core_count() -> u32 {
+    unsafe { spdk::spdk_env_get_core_count() }
+}
+
+/// spdk_env_get_current_core()
+///
+/// Returns `None` when the caller is not running on an SPDK core.
+pub fn current_core() -> Option<u32> {
+    match unsafe { spdk::spdk_env_get_current_core() } {
+        std::u32::MAX => None,
+        core => Some(core),
+    }
+}
+
+/// spdk_process_is_primary()
+///
+/// False in a secondary process, which shares the memory of the primary
+/// process with the same `shm_id`.
+pub fn process_is_primary() -> bool {
+    unsafe { spdk::spdk_process_is_primary() }
+}
+
+/// spdk_env_get_socket_id()
+pub fn socket_id(core: u32) -> u32 {
+    unsafe { spdk::spdk_env_get_socket_id(core) }
+}
+
+/// Iterates over the cores available to the application, see `cores()`.
+pub struct Cores {
+    next: u32,
+}
+
+impl Iterator for Cores {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        if self.next == std::u32::MAX {
+            return None;
+        }
+        let core = self.next;
+        self.next = unsafe { spdk::spdk_env_get_next_core(core) };
+        Some(core)
+    }
+}
+
+/// spdk_env_get_first_core() / spdk_env_get_next_core()
+pub fn cores() -> Cores {
+    Cores {
+        next: unsafe { spdk::spdk_env_get_first_core() },
+    }
+}
+
+/// spdk_env_thread_launch_pinned()
+///
+/// Runs `f` on a new thread pinned to `core`. The value returned by `f` is
+/// the thread's exit status.
+pub fn launch_pinned<F>(core: u32, f: F) -> Result<(), Error>
+where
+    F: FnOnce() -> i32 + Send + 'static,
+{
+    extern "C" fn launch_wrapper<F>(closure: *mut c_void) -> c_int
+    where
+        F: FnOnce() -> i32,
+    {
+        let f = unsafe { Box::from_raw(closure as *mut F) };
+        f()
+    }
+
+    let f_pointer = Box::into_raw(Box::new(f));
+    let rc = unsafe {
+        spdk::spdk_env_thread_launch_pinned(
+            core,
+            Some(launch_wrapper::<F>),
+            f_pointer as *mut c_void,
+        )
+    };
+    if rc != 0 {
+        // The thread was never started, so the closure is still ours.
+        drop(unsafe { Box::from_raw(f_pointer) });
+        return Err(EnvError::LaunchError(core, rc))?;
+    }
+    Ok(())
+}
+
+/// spdk_env_thread_wait_all()
+///
+/// Waits for every thread started with `launch_pinned` to finish.
+pub fn wait_all() {
+    unsafe { spdk::spdk_env_thread_wait_all() }
+}
@@ -1,39 +1,378 @@
 use crate::bdev::{SpdkBdev, SpdkBdevDesc};
+use crate::cpuset::CpuSet;
+use crate::env;
+use crate::io_channel::{poller_register_timed, PollerHandle};
+use crate::log::{self, LogLevel};
 use spdk;
 
 use failure::Error;
+use futures::channel::oneshot;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::ffi::{CStr, CString};
-use std::os::raw::{c_char, c_void};
+use std::future::Future;
+use std::mem;
+use std::os::raw::{c_char, c_int, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
 use std::ptr;
+use std::sync::Mutex;
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+/// How long `executor::start_async` waits for the app to wind down after a
+/// shutdown request, unless set with `SpdkAppOpts::grace_period`.
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(5);
 
 #[derive(Debug, Fail)]
 enum AppError {
     #[fail(display = "Spdk failed to start: {}", _0)]
     StartupError(i32),
+
+    #[fail(display = "Could not get the running config: {}", _0)]
+    RunningConfigError(i32),
+}
+
+#[derive(Debug, Fail)]
+pub enum EventError {
+    #[fail(display = "Could not allocate an event for core {}", _0)]
+    AllocationError(u32),
+
+    #[fail(display = "Event on core {} did not complete", _0)]
+    Cancelled(u32),
+}
+
+#[derive(Debug, Fail)]
+pub enum ParseError {
+    #[fail(display = "Help was requested")]
+    Help(),
+
+    #[fail(display = "Invalid command line arguments")]
+    Invalid(),
+
+    #[fail(display = "Invalid value for -{}: {}", _0, _1)]
+    Rejected(char, String),
+
+    #[fail(display = "Argument contains a NUL byte: {}", _0)]
+    NulByte(String),
+
+    #[fail(display = "Arguments were already parsed")]
+    AlreadyParsed(),
+}
+
+// Shutdown callback of the running app, see `SpdkAppOpts::shutdown_cb`.
+thread_local!(static SHUTDOWN_CB: RefCell<Option<Box<dyn FnMut()>>> = RefCell::new(None));
+
+// Callbacks of the ongoing `SpdkAppOpts::parse_args` call.
+thread_local!(static PARSE_STATE: RefCell<Option<ParseState>> = RefCell::new(None));
+
+// Stops the app once the grace period following a shutdown request is over.
+thread_local!(static GRACE_POLLER: RefCell<Option<PollerHandle>> = RefCell::new(None));
+
+lazy_static! {
+    static ref SHUTDOWN: Mutex<ShutdownState> = Mutex::new(ShutdownState::default());
 }
 
 #[derive(Default)]
-pub struct SpdkAppOpts(spdk::spdk_app_opts);
+struct ShutdownState {
+    requested: bool,
+    /// Set if the grace period ran out before the app stopped.
+    timed_out: bool,
+    /// Wakers of the pending `ShutdownSignal`s, by slot.
+    waiters: HashMap<u64, Waker>,
+    /// Next slot handed out to a `ShutdownSignal`.
+    next_slot: u64,
+    hooks: Vec<Box<dyn FnOnce() + Send>>,
+    /// Core the shutdown callback ran on, where late hooks are sent.
+    master_core: Option<u32>,
+}
+
+struct ParseState {
+    parse: Box<dyn FnMut(char, Option<&str>) -> Result<(), Error>>,
+    usage: Box<dyn FnMut()>,
+    /// First error returned by `parse`.
+    error: Option<Error>,
+    /// Panic of `parse` or `usage`, resumed once spdk_app_parse_args() returns.
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+/// spdk_app_opts
+///
+/// The options own every string they point to, so they stay valid for as
+/// long as the app runs.
+#[derive(Default)]
+pub struct SpdkAppOpts {
+    raw: spdk::spdk_app_opts,
+    name: Option<CString>,
+    config_file: Option<CString>,
+    rpc_addr: Option<CString>,
+    reactor_mask: Option<CString>,
+    tpoint_group_mask: Option<CString>,
+    hugedir: Option<CString>,
+    log_level: Option<LogLevel>,
+    shutdown_cb: Option<Box<dyn FnMut()>>,
+    pub(crate) grace_period: Option<Duration>,
+    /// Arguments given to `parse_args`, the string options point into them.
+    args: Vec<CString>,
+    argv: Vec<*mut c_char>,
+}
 
 impl SpdkAppOpts {
+    /// spdk_app_opts_init()
     pub fn new() -> Self {
-        let mut opts: spdk::spdk_app_opts = Default::default();
+        let mut opts = SpdkAppOpts::default();
         unsafe {
-            spdk::spdk_app_opts_init(&mut opts as *mut spdk::spdk_app_opts);
+            spdk::spdk_app_opts_init(&mut opts.raw as *mut spdk::spdk_app_opts);
         }
-        SpdkAppOpts(opts)
+        opts
+    }
+
+    pub fn name(&mut self, name: &str) -> &mut Self {
+        set_string(&mut self.raw.name, &mut self.name, name);
+        self
+    }
+
+    pub fn config_file(&mut self, config_file: &str) -> &mut Self {
+        set_string(
+            &mut self.raw.config_file,
+            &mut self.config_file,
+            config_file,
+        );
+        self
+    }
+
+    /// Address of the JSON-RPC server, a UNIX socket path or `ip:port`.
+    pub fn rpc_addr(&mut self, rpc_addr: &str) -> &mut Self {
+        set_string(&mut self.raw.rpc_addr, &mut self.rpc_addr, rpc_addr);
+        self
+    }
+
+    /// Cores to run reactors on.
+    pub fn reactor_mask(&mut self, mask: &CpuSet) -> &mut Self {
+        set_string(
+            &mut self.raw.reactor_mask,
+            &mut self.reactor_mask,
+            &mask.to_string(),
+        );
+        self
+    }
+
+    /// Tracepoint groups to enable, as a hexadecimal mask.
+    pub fn tpoint_group_mask(&mut self, mask: u64) -> &mut Self {
+        set_string(
+            &mut self.raw.tpoint_group_mask,
+            &mut self.tpoint_group_mask,
+            &format!("0x{:x}", mask),
+        );
+        self
+    }
+
+    /// Directory hugetlbfs is mounted on.
+    pub fn hugedir(&mut self, hugedir: &str) -> &mut Self {
+        set_string(&mut self.raw.hugedir, &mut self.hugedir, hugedir);
+        self
     }
 
-    pub fn name(&mut self, name: &str) {
-        self.0.name = CString::new(name)
-            .expect("Couldn't create a string")
-            .into_raw()
+    /// Shared memory id, for multi-process setups.
+    pub fn shm_id(&mut self, shm_id: i32) -> &mut Self {
+        self.raw.shm_id = shm_id;
+        self
     }
 
-    pub fn config_file(&mut self, config_file: &str) {
-        self.0.config_file = CString::new(config_file)
-            .expect("Couldn't create a string")
-            .into_raw()
+    /// Amount of memory to reserve, in megabytes.
+    pub fn mem_size(&mut self, mem_size: i32) -> &mut Self {
+        self.raw.mem_size = mem_size;
+        self
+    }
+
+    /// Core the app is started on, it must be part of the reactor mask.
+    pub fn master_core(&mut self, master_core: i32) -> &mut Self {
+        self.raw.master_core = master_core;
+        self
+    }
+
+    /// Level at which messages are logged, applied when the app starts.
+    pub fn log_level(&mut self, level: LogLevel) -> &mut Self {
+        self.log_level = Some(level);
+        self
+    }
+
+    /// Level at which messages are also printed to stderr.
+    pub fn print_level(&mut self, level: LogLevel) -> &mut Self {
+        self.raw.print_level = level.to_raw();
+        self
+    }
+
+    /// Maximum time a reactor sleeps when it has nothing to do.
+    pub fn max_delay(&mut self, max_delay: Duration) -> &mut Self {
+        self.raw.max_delay_us = max_delay.as_micros() as u64;
+        self
+    }
+
+    /// Called on the master core instead of stopping the app when it receives
+    /// SIGINT or SIGTERM. The callback should eventually call `app_stop`.
+    pub fn shutdown_cb<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnMut() + 'static,
+    {
+        extern "C" fn shutdown_wrapper() {
+            SHUTDOWN_CB.with(|cb| {
+                if let Some(ref mut f) = *cb.borrow_mut() {
+                    catch_and_log("Shutdown callback", f)
+                }
+            })
+        }
+
+        self.shutdown_cb = Some(Box::new(f));
+        self.raw.shutdown_cb = Some(shutdown_wrapper);
+        self
+    }
+
+    /// spdk_app_parse_args()
+    ///
+    /// Fills in the options from the standard SPDK flags found in `args`,
+    /// which starts with the program name. Flags listed in `getopt_str`, in
+    /// getopt(3) syntax, are handed to `parse` along with their argument,
+    /// and `usage` prints their help after SPDK's own.
+    ///
+    /// The options keep pointing into `args`, so this can only be called once.
+    /// A panic in `parse` or `usage` is resumed once SPDK is done parsing.
+    pub fn parse_args<I, S, P, U>(
+        &mut self,
+        args: I,
+        getopt_str: &str,
+        parse: P,
+        usage: U,
+    ) -> Result<&mut Self, Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+        P: FnMut(char, Option<&str>) -> Result<(), Error> + 'static,
+        U: FnMut() + 'static,
+    {
+        extern "C" fn parse_wrapper(ch: c_int, arg: *mut c_char) -> c_int {
+            PARSE_STATE.with(|state| {
+                let mut state = state.borrow_mut();
+                let state = match state.as_mut() {
+                    Some(state) if state.panic.is_none() => state,
+                    _ => return -libc::EINVAL,
+                };
+                let arg = if arg.is_null() {
+                    None
+                } else {
+                    Some(
+                        unsafe { CStr::from_ptr(arg) }
+                            .to_string_lossy()
+                            .into_owned(),
+                    )
+                };
+                let parse = &mut state.parse;
+                // Unwinding into SPDK is undefined behavior.
+                let res = panic::catch_unwind(AssertUnwindSafe(|| {
+                    parse(ch as u8 as char, arg.as_ref().map(String::as_str))
+                }));
+                match res {
+                    Ok(Ok(())) => 0,
+                    Ok(Err(e)) => {
+                        if state.error.is_none() {
+                            state.error = Some(e);
+                        }
+                        -libc::EINVAL
+                    }
+                    Err(panic) => {
+                        state.panic = Some(panic);
+                        -libc::EINVAL
+                    }
+                }
+            })
+        }
+
+        extern "C" fn usage_wrapper() {
+            PARSE_STATE.with(|state| {
+                if let Some(ref mut state) = *state.borrow_mut() {
+                    if state.panic.is_some() {
+                        return;
+                    }
+                    let usage = &mut state.usage;
+                    if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| usage())) {
+                        state.panic = Some(panic);
+                    }
+                }
+            })
+        }
+
+        if !self.argv.is_empty() {
+            return Err(ParseError::AlreadyParsed())?;
+        }
+
+        let mut owned_args = Vec::new();
+        for arg in args {
+            let arg = arg.into();
+            match CString::new(arg.clone()) {
+                Ok(arg) => owned_args.push(arg),
+                Err(_) => return Err(ParseError::NulByte(arg))?,
+            }
+        }
+        let getopt_cstring = match CString::new(getopt_str) {
+            Ok(getopt_cstring) => getopt_cstring,
+            Err(_) => return Err(ParseError::NulByte(getopt_str.to_string()))?,
+        };
+
+        // getopt() may reorder the pointers, but never touches the strings.
+        self.args = owned_args;
+        self.argv = self
+            .args
+            .iter()
+            .map(|arg| arg.as_ptr() as *mut c_char)
+            .collect();
+        self.argv.push(ptr::null_mut());
+
+        PARSE_STATE.with(|state| {
+            *state.borrow_mut() = Some(ParseState {
+                parse: Box::new(parse),
+                usage: Box::new(usage),
+                error: None,
+                panic: None,
+            })
+        });
+        let rc = unsafe {
+            spdk::spdk_app_parse_args(
+                self.args.len() as c_int,
+                self.argv.as_mut_ptr(),
+                &mut self.raw,
+                getopt_cstring.as_ptr(),
+                ptr::null_mut(),
+                Some(parse_wrapper),
+                Some(usage_wrapper),
+            )
+        };
+        let state = PARSE_STATE
+            .with(|state| state.borrow_mut().take())
+            .expect("Parse state went away");
+
+        if let Some(panic) = state.panic {
+            panic::resume_unwind(panic);
+        }
+        if let Some(e) = state.error {
+            return Err(e);
+        }
+        match rc {
+            spdk::spdk_app_parse_args_rvals_SPDK_APP_PARSE_ARGS_SUCCESS => Ok(self),
+            spdk::spdk_app_parse_args_rvals_SPDK_APP_PARSE_ARGS_HELP => Err(ParseError::Help())?,
+            _ => Err(ParseError::Invalid())?,
+        }
+    }
+
+    /// Time `executor::start_async` gives the app to complete after a shutdown
+    /// request, before stopping it with a failure status.
+    pub fn grace_period(&mut self, grace_period: Duration) -> &mut Self {
+        self.grace_period = Some(grace_period);
+        self
+    }
+
+    pub fn to_raw(&self) -> *const spdk::spdk_app_opts {
+        &self.raw
     }
 
     pub fn start<F>(mut self, f: F) -> Result<(), Error>
@@ -50,11 +389,17 @@ impl SpdkAppOpts {
             unsafe { (*opt_closure)() }
         }
 
+        if let Some(level) = self.log_level {
+            log::set_level(level);
+        }
+        // spdk_app_start() runs the master reactor on this thread, which is
+        // where the shutdown callback is called from.
+        let shutdown_cb = self.shutdown_cb.take();
+        SHUTDOWN_CB.with(|cb| *cb.borrow_mut() = shutdown_cb);
+
         let ret = unsafe {
-            let self_ref = &mut self;
-            let opts_ref = &mut self_ref.0;
             spdk::spdk_app_start(
-                opts_ref as *mut spdk::spdk_app_opts,
+                &mut self.raw as *mut spdk::spdk_app_opts,
                 Some(start_wrapper::<F>),
                 user_data,
                 ptr::null_mut(),
@@ -62,11 +407,9 @@ impl SpdkAppOpts {
         };
 
         unsafe {
-            //            if (context.buff != ptr::null_mut()) {
-            //                spdk::spdk_dma_free(context.buff as *mut c_void);
-            //            }
             spdk::spdk_app_fini();
         }
+        SHUTDOWN_CB.with(|cb| drop(cb.borrow_mut().take()));
 
         if ret == 0 {
             Ok(())
@@ -76,21 +419,305 @@ impl SpdkAppOpts {
     }
 }
 
+/// Points `raw` at a copy of `value` owned by `owned`, releasing the previous one.
+fn set_string(raw: &mut *const c_char, owned: &mut Option<CString>, value: &str) {
+    let value = CString::new(value).expect("Couldn't create a string");
+    *raw = value.as_ptr();
+    *owned = Some(value);
+}
+
+/// Registers `f` to run on the master core when the app starts shutting down,
+/// see `executor::start_async`. If the shutdown already started, `f` is sent
+/// to the master core as an event instead. A panic in `f` is caught and
+/// logged.
+pub fn on_shutdown<F>(f: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    let mut state = SHUTDOWN.lock().unwrap();
+    match (state.requested, state.master_core) {
+        (true, Some(core)) => {
+            drop(state);
+            if let Err(e) = send_on(core, f) {
+                log::log(
+                    LogLevel::Error,
+                    file!(),
+                    line!(),
+                    module_path!(),
+                    &format!("Shutdown hook dropped: {}", e),
+                );
+            }
+        }
+        (true, None) => {
+            drop(state);
+            catch_and_log("Shutdown hook", f);
+        }
+        (false, _) => state.hooks.push(Box::new(f)),
+    }
+}
+
+/// spdk_app_start_shutdown()
+///
+/// Requests a shutdown, as if the app had received SIGINT.
+pub fn start_shutdown() {
+    unsafe { spdk::spdk_app_start_shutdown() }
+}
+
+/// Whether a shutdown was requested.
+pub fn is_shutting_down() -> bool {
+    SHUTDOWN.lock().unwrap().requested
+}
+
+/// Resolves once a shutdown is requested. Long running tasks can race their
+/// work against it to stop within the grace period.
+pub fn shutdown_signal() -> ShutdownSignal {
+    ShutdownSignal { slot: None }
+}
+
+/// Future returned by `shutdown_signal`.
+pub struct ShutdownSignal {
+    /// Where the waker of the last pending poll is kept.
+    slot: Option<u64>,
+}
+
+impl Future for ShutdownSignal {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = Pin::get_mut(self);
+        let mut state = SHUTDOWN.lock().unwrap();
+        if state.requested {
+            return Poll::Ready(());
+        }
+        let slot = match this.slot {
+            Some(slot) => slot,
+            None => {
+                let slot = state.next_slot;
+                state.next_slot += 1;
+                this.slot = Some(slot);
+                slot
+            }
+        };
+        state.waiters.insert(slot, cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for ShutdownSignal {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot {
+            SHUTDOWN.lock().unwrap().waiters.remove(&slot);
+        }
+    }
+}
+
+/// Shutdown callback installed by `executor::start_async`: signals the app,
+/// runs the hooks and stops the reactors once `grace_period` is over. A
+/// second request stops them right away.
+pub(crate) fn begin_shutdown(grace_period: Duration) {
+    let (waiters, hooks) = {
+        let mut state = SHUTDOWN.lock().unwrap();
+        if state.requested {
+            drop(state);
+            app_stop(false);
+            return;
+        }
+        state.requested = true;
+        state.master_core = env::current_core();
+        (
+            mem::replace(&mut state.waiters, HashMap::new()),
+            mem::replace(&mut state.hooks, Vec::new()),
+        )
+    };
+
+    for (_, waker) in waiters {
+        waker.wake();
+    }
+    for hook in hooks {
+        catch_and_log("Shutdown hook", hook);
+    }
+
+    let stopped = Cell::new(false);
+    let poller = poller_register_timed(
+        move || {
+            if stopped.replace(true) {
+                return false;
+            }
+            SHUTDOWN.lock().unwrap().timed_out = true;
+            app_stop(false);
+            true
+        },
+        grace_period,
+    );
+    GRACE_POLLER.with(|slot| *slot.borrow_mut() = Some(poller));
+}
+
+/// Whether the app was stopped because the grace period ran out.
+pub(crate) fn grace_period_expired() -> bool {
+    SHUTDOWN.lock().unwrap().timed_out
+}
+
+/// Clears the shutdown state once the app has stopped.
+pub(crate) fn reset_shutdown() {
+    // The reactors are gone, so the poller cannot be unregistered anymore.
+    GRACE_POLLER.with(|slot| mem::forget(slot.borrow_mut().take()));
+    let mut state = SHUTDOWN.lock().unwrap();
+    // Signals that outlive the app keep their slot, do not hand it out again.
+    let next_slot = state.next_slot;
+    *state = ShutdownState {
+        next_slot,
+        ..ShutdownState::default()
+    };
+}
+
+/// spdk_app_get_running_config()
+///
+/// Configuration of the running app in the legacy INI format, with `name` in
+/// its header.
+pub fn running_config(name: &str) -> Result<String, Error> {
+    let name_cstring = CString::new(name).expect("Couldn't create a string");
+    let mut config: *mut c_char = ptr::null_mut();
+    let rc = unsafe {
+        spdk::spdk_app_get_running_config(&mut config, name_cstring.as_ptr() as *mut c_char)
+    };
+    if rc != 0 || config.is_null() {
+        return Err(AppError::RunningConfigError(rc))?;
+    }
+    let text = unsafe { CStr::from_ptr(config) }
+        .to_string_lossy()
+        .into_owned();
+    unsafe { spdk::free(config as *mut c_void) };
+    Ok(text)
+}
+
+/// spdk_app_usage()
+///
+/// Prints the help of the standard SPDK flags.
+pub fn usage() {
+    unsafe { spdk::spdk_app_usage() }
+}
+
 pub fn app_stop(success: bool) {
     unsafe {
         spdk::spdk_app_stop(if success { 0 } else { -1 });
     };
 }
 
-//impl Drop for SpdAppOpts {
-//    fn drop(&mut self) {
-//        drop_if_not_null(self.0.name as *mut c_char);
-//        drop_if_not_null(self.0.config_file as *mut c_char);
-//    }
-//}
-//
-//fn drop_if_not_null(string: *mut c_char) {
-//    if !string.is_null() {
-//        unsafe { CString::from_raw(string as *mut c_char) };
-//    }
-//}
+/// spdk_event_allocate() / spdk_event_call()
+///
+/// Runs `f` on the reactor of `lcore` without waiting for it to complete.
+/// Unlike messages, events do not require an SPDK thread, so this can be
+/// used during startup. A panic in `f` is caught and logged on that reactor.
+pub fn send_on<F>(lcore: u32, f: F) -> Result<(), Error>
+where
+    F: FnOnce() + Send + 'static,
+{
+    extern "C" fn event_wrapper<F>(closure: *mut c_void, _: *mut c_void)
+    where
+        F: FnOnce(),
+    {
+        let f = unsafe { Box::from_raw(closure as *mut F) };
+        catch_and_log("Event", f)
+    }
+
+    let f_pointer = Box::into_raw(Box::new(f));
+    let event = unsafe {
+        spdk::spdk_event_allocate(
+            lcore,
+            Some(event_wrapper::<F>),
+            f_pointer as *mut c_void,
+            ptr::null_mut(),
+        )
+    };
+    if event.is_null() {
+        // The event was never allocated, so the closure is still ours.
+        drop(unsafe { Box::from_raw(f_pointer) });
+        return Err(EventError::AllocationError(lcore))?;
+    }
+    unsafe { spdk::spdk_event_call(event) };
+    Ok(())
+}
+
+/// Runs `f`, logging its panic instead of unwinding into SPDK, which is
+/// undefined behavior.
+fn catch_and_log<F>(what: &str, f: F)
+where
+    F: FnOnce(),
+{
+    if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(f)) {
+        log::log(
+            LogLevel::Error,
+            file!(),
+            line!(),
+            module_path!(),
+            &format!(
+                "{} panicked: {}",
+                what,
+                crate::executor::panic_message(&panic)
+            ),
+        );
+    }
+}
+
+/// Runs `f` on the reactor of `lcore` and resolves with its result, or with
+/// `EventError::Cancelled` if it panics.
+///
+/// When called from a reactor, the result is handed back through a follow-up
+/// event on the calling core, so the awaiting task is always woken up on its
+/// own core. Calls can be chained by awaiting them one after another.
+pub async fn call_on<F, R>(lcore: u32, f: F) -> Result<R, Error>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let (sender, receiver) = oneshot::channel();
+    let home = env::current_core();
+    send_on(lcore, move || {
+        let result = f();
+        match home {
+            Some(home) if home != lcore => {
+                // If this fails the sender is dropped and the caller sees a cancellation.
+                let _ = send_on(home, move || {
+                    let _ = sender.send(result);
+                });
+            }
+            _ => {
+                let _ = sender.send(result);
+            }
+        }
+    })?;
+
+    match receiver.await {
+        Ok(result) => Ok(result),
+        Err(_) => Err(EventError::Cancelled(lcore))?,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use futures::task::noop_waker;
+    use hamcrest2::prelude::*;
+
+    #[test]
+    fn shutdown_signal_keeps_one_waker_until_dropped() {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let waiters = || SHUTDOWN.lock().unwrap().waiters.len();
+
+        let mut signal = shutdown_signal();
+        assert_that!(
+            Pin::new(&mut signal).poll(&mut cx),
+            is(equal_to(Poll::Pending))
+        );
+        assert_that!(
+            Pin::new(&mut signal).poll(&mut cx),
+            is(equal_to(Poll::Pending))
+        );
+        assert_that!(waiters(), is(equal_to(1)));
+
+        drop(signal);
+        assert_that!(waiters(), is(equal_to(0)));
+    }
+}
@@ -1,39 +1,88 @@
-use futures::future::Future;
+use crate::env;
+use crate::event;
+use crate::event::SpdkAppOpts;
+use crate::io_channel::{poller_register, PollerHandle};
+use crate::log::{self, LogLevel};
+use crate::thread;
+use crate::thread::SpdkThread;
+use crate::time::TickInstant;
+
+use failure::Error;
+use futures::channel::oneshot;
+use futures::future::FutureExt;
 use futures::future::LocalFutureObj;
 use futures::task::LocalSpawn;
 use futures::task::SpawnError;
-use futures::task::{LocalWaker, Poll, UnsafeWake, Waker};
+use std::any::Any;
 use std::cell::Cell;
 use std::cell::RefCell;
 use std::cell::UnsafeCell;
 use std::collections::VecDeque;
+use std::future::Future;
+use std::mem;
+use std::num::NonZeroUsize;
+use std::panic;
+use std::panic::AssertUnwindSafe;
 use std::pin::Pin;
-use std::ptr::NonNull;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use std::thread::ThreadId;
+use std::time::Duration;
 
 /// Tracks the executor for the current execution context.
 /// TODO: Use UnsafeCell, since we can guarantee correct implementation
 thread_local!(static CURRENT_EXECUTOR: RefCell<Option<CurrentThreadExecutor>> = RefCell::new(None));
 
-#[allow(dead_code)]
-struct LocalExecutor {}
+// Keeps the executor installed on behalf of the application (by `block_on_app`
+// or `install_on_all_cores`) alive while the app runs.
+thread_local!(static APP_ENTER: RefCell<Option<Enter>> = RefCell::new(None));
+
+/// Ids handed out to spawned tasks, unique across threads.
+static NEXT_TASK_ID: AtomicU64 = AtomicU64::new(1);
+
+/// See `stranded_tasks`.
+static STRANDED_TASKS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Fail)]
+pub enum ExecutorError {
+    #[fail(display = "Application stopped before the main future completed")]
+    AppStopped(),
+
+    #[fail(display = "Task panicked: {}", _0)]
+    Panicked(String),
+
+    #[fail(display = "Application did not complete within its grace period")]
+    GracePeriodExpired(),
+}
+
+#[derive(Debug, Fail)]
+pub enum JoinError {
+    #[fail(display = "Task was cancelled")]
+    Cancelled(),
+
+    #[fail(display = "Task panicked: {}", _0)]
+    Panicked(String),
+}
+
+/// `LocalSpawn` implementation spawning onto the executor of the current thread.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LocalExecutor {}
 
 impl LocalExecutor {
-    #[allow(dead_code)]
-    fn new() -> Self {
+    pub fn new() -> Self {
         LocalExecutor {}
     }
 }
 
 impl LocalSpawn for LocalExecutor {
-    fn spawn_local_obj(&mut self, future: LocalFutureObj<'static, ()>) -> Result<(), SpawnError> {
+    fn spawn_local_obj(&self, future: LocalFutureObj<'static, ()>) -> Result<(), SpawnError> {
         with_default_no_fail(|maybe_executor| match maybe_executor {
             Some(ref executor) => {
-                let task = Rc::new(TaskHandle {
-                    task: UnsafeCell::new(Some(TaskContext::new(future))),
-                    queued: Cell::new(true),
-                });
-                (&mut executor.tq.borrow_mut()).add_task(task);
+                // The handle is dropped, which detaches the task.
+                let (joinable, _) = joinable(future);
+                executor.spawn_joinable(joinable, None, Priority::Normal);
                 Ok(())
             }
             None => Err(SpawnError::shutdown()),
@@ -46,80 +95,426 @@ pub struct CurrentThreadExecutor {
     // asks to be woken up while we are in `pure_poll`.
     // TODO: use UnsafeCell, since we know the usage is correct and apparently there is some overhead.
     tq: RefCell<TaskQueue>,
+    /// Every task spawned on this executor, for `tasks()`. Dead entries are
+    /// pruned whenever the registry would have to grow.
+    tasks: RefCell<Vec<Weak<TaskHandle>>>,
+    instrumentation: Cell<Option<Instrumentation>>,
+    /// Maximum number of tasks polled by one call to `pure_poll`.
+    budget: Cell<Option<NonZeroUsize>>,
+    /// Tasks that called `yield_now`, woken up when `pure_poll` returns.
+    yielded: RefCell<Vec<Waker>>,
 }
 
 impl CurrentThreadExecutor {
     fn new() -> CurrentThreadExecutor {
         CurrentThreadExecutor {
             tq: RefCell::new(TaskQueue::new()),
+            tasks: RefCell::new(Vec::new()),
+            instrumentation: Cell::new(None),
+            budget: Cell::new(None),
+            yielded: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Registers a new task and queues it for its first poll.
+    fn spawn_task(&self, task: Arc<TaskHandle>) {
+        {
+            let mut tasks = self.tasks.borrow_mut();
+            if tasks.len() == tasks.capacity() {
+                tasks.retain(|task| task.upgrade().is_some());
+            }
+            tasks.push(Arc::downgrade(&task));
+        }
+        self.tq.borrow_mut().add_task(task);
+    }
+
+    /// Registers a task running `joinable`, which its `JoinHandle` wakes up
+    /// on abort.
+    fn spawn_joinable<F>(&self, joinable: Joinable<F>, name: Option<String>, priority: Priority)
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        let abort = joinable.abort.clone();
+        let task = TaskHandle::new(TaskContext::new(joinable), name, priority);
+        *abort.task.lock().unwrap() = Arc::downgrade(&task);
+        self.spawn_task(task);
+    }
+}
+
+/// Limits how many tasks a single call to `pure_poll` polls on the executor
+/// of the current thread, so that a busy executor does not starve the other
+/// pollers of its reactor. The remaining tasks are polled on the next call.
+/// `None`, the default, drains the queue every time.
+pub fn set_poll_budget(budget: Option<NonZeroUsize>) {
+    with_default(|executor| executor.budget.set(budget))
+}
+
+pub fn poll_budget() -> Option<NonZeroUsize> {
+    with_default(|executor| executor.budget.get())
+}
+
+/// Scheduling class of a task. High priority tasks are always polled before
+/// normal ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    /// Latency sensitive tasks, such as I/O submission.
+    High,
+    /// Everything else, including background work.
+    Normal,
+}
+
+impl Default for Priority {
+    fn default() -> Priority {
+        Priority::Normal
+    }
+}
+
+/// Yields to the other tasks and to the pollers of the reactor: the current
+/// task is polled again on the next call to `pure_poll` only.
+pub fn yield_now() -> YieldNow {
+    YieldNow { yielded: false }
+}
+
+/// Future returned by `yield_now`.
+pub struct YieldNow {
+    yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = Pin::get_mut(self);
+        if this.yielded {
+            return Poll::Ready(());
+        }
+        this.yielded = true;
+
+        let waker = cx.waker().clone();
+        let deferred = with_default_no_fail(|maybe_executor| match maybe_executor {
+            Some(executor) => {
+                executor.yielded.borrow_mut().push(waker);
+                Ok(())
+            }
+            None => Err(waker),
+        });
+        if let Err(waker) = deferred {
+            waker.wake();
         }
+        Poll::Pending
     }
 }
 
+#[derive(Clone, Copy)]
+struct Instrumentation {
+    slow_poll_threshold: Option<Duration>,
+}
+
+/// Measures how long each poll of each task takes on the executor of the
+/// current thread, see `tasks()`. If `slow_poll_threshold` is set, polls
+/// taking longer than that log a warning naming the offending task, and are
+/// counted in `TaskSnapshot::slow_polls`.
+///
+/// Poll counts are always kept, timing is off until this is called.
+pub fn enable_instrumentation(slow_poll_threshold: Option<Duration>) {
+    with_default(|executor| {
+        executor.instrumentation.set(Some(Instrumentation {
+            slow_poll_threshold,
+        }))
+    })
+}
+
+/// Stops measuring poll times, the stats gathered so far are kept.
+pub fn disable_instrumentation() {
+    with_default(|executor| executor.instrumentation.set(None))
+}
+
+/// Whether poll times are being measured on the current thread.
+pub fn instrumentation_enabled() -> bool {
+    with_default(|executor| executor.instrumentation.get().is_some())
+}
+
+/// Statistics of a live task, see `tasks()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TaskSnapshot {
+    pub id: u64,
+    pub name: Option<String>,
+    /// Number of times the task has been polled.
+    pub polls: u64,
+    /// Total time spent polling the task while instrumentation was enabled.
+    pub busy: Duration,
+    /// Longest single poll while instrumentation was enabled.
+    pub longest_poll: Duration,
+    /// Polls that took longer than the `slow_poll_threshold` given to
+    /// `enable_instrumentation`.
+    pub slow_polls: u64,
+    /// Whether the task is waiting in the run queue.
+    pub queued: bool,
+    pub priority: Priority,
+}
+
+/// Snapshot of every live task of the executor of the current thread, in
+/// spawn order.
+pub fn tasks() -> Vec<TaskSnapshot> {
+    with_default(|executor| {
+        let mut tasks = executor.tasks.borrow_mut();
+        tasks.retain(|task| task.upgrade().is_some());
+        tasks
+            .iter()
+            .filter_map(Weak::upgrade)
+            .filter(|task| task.is_alive())
+            .map(|task| task.snapshot())
+            .collect()
+    })
+}
+
+/// Number of times a task was woken up or released on another thread than
+/// its home thread, while the home thread is not an SPDK thread. Nothing can
+/// be forwarded there: the wake-up is lost, and a released task is leaked
+/// rather than dropped on the wrong thread.
+pub fn stranded_tasks() -> u64 {
+    STRANDED_TASKS.load(Ordering::Relaxed)
+}
+
 struct TaskQueue {
-    q: VecDeque<Rc<TaskHandle>>,
+    high: VecDeque<Arc<TaskHandle>>,
+    normal: VecDeque<Arc<TaskHandle>>,
 }
 
 impl TaskQueue {
     #[inline]
     fn new() -> TaskQueue {
-        TaskQueue { q: VecDeque::new() }
+        TaskQueue {
+            high: VecDeque::new(),
+            normal: VecDeque::new(),
+        }
     }
 
     #[inline]
-    fn add_task(&mut self, task: Rc<TaskHandle>) {
-        self.q.push_back(task)
+    fn add_task(&mut self, task: Arc<TaskHandle>) {
+        match task.priority {
+            Priority::High => self.high.push_back(task),
+            Priority::Normal => self.normal.push_back(task),
+        }
     }
 
-    /// Polls the next `TaskHandle` and gets it as a raw pointer from `Rc`.
+    /// Polls the next `TaskHandle` and gets it as a raw pointer from `Arc`.
     /// The counter is not incremented, the pointer owns one reference.
     #[inline]
-    fn poll_task_from_rc(&mut self) -> Option<*const TaskHandle> {
-        self.q.pop_front().map(Rc::into_raw)
+    fn poll_task_from_arc(&mut self) -> Option<*const TaskHandle> {
+        self.high
+            .pop_front()
+            .or_else(|| self.normal.pop_front())
+            .map(Arc::into_raw)
     }
 }
 
 struct TaskHandle {
     task: UnsafeCell<Option<TaskContext>>,
-    queued: Cell<bool>,
-}
-
-// TODO: Revisit this, even though we only ever want to run local futures,
-// however I'll need to figure out how to have local counters that send a message once the value reaches zero
-// to the original core.
-//
-// It is likely that CurrentThreadExecutor will need to store it's ID and a function that will let it notify
-// other cores (that just maps to dpdk queues); Then TaskHandle also needs to know which core it is on
-// and then UnsafeWake impls will actually do nested Rcs -> there is basically a counter on each core
-// that has access and if a particular core's handle goes to 0, it notifies the core that sent it the handle,
-// so they form a cycle?
-//
-// Not sure if this is even possible, so for now let's pretend we'll never send handles to other cores.
+    queued: AtomicBool,
+    /// Thread the task was spawned on; the task is only polled and dropped there.
+    home: ThreadId,
+    /// SPDK thread running on `home`, used to route wake-ups from other threads.
+    home_thread: Option<SpdkThread>,
+    id: u64,
+    name: Option<String>,
+    priority: Priority,
+    /// Only touched on `home`, by `pure_poll` and `tasks()`.
+    stats: RefCell<TaskStats>,
+}
+
+#[derive(Default)]
+struct TaskStats {
+    done: bool,
+    polls: u64,
+    busy: Duration,
+    longest_poll: Duration,
+    slow_polls: u64,
+}
+
+impl TaskHandle {
+    fn new(task: TaskContext, name: Option<String>, priority: Priority) -> Arc<TaskHandle> {
+        Arc::new(TaskHandle {
+            task: UnsafeCell::new(Some(task)),
+            queued: AtomicBool::new(true),
+            home: std::thread::current().id(),
+            home_thread: SpdkThread::current(),
+            id: NEXT_TASK_ID.fetch_add(1, Ordering::Relaxed),
+            name,
+            priority,
+            stats: RefCell::new(TaskStats::default()),
+        })
+    }
+
+    /// False once the future has completed, wakers may keep the handle around.
+    fn is_alive(&self) -> bool {
+        !self.stats.borrow().done
+    }
+
+    fn snapshot(&self) -> TaskSnapshot {
+        let stats = self.stats.borrow();
+        TaskSnapshot {
+            id: self.id,
+            name: self.name.clone(),
+            polls: stats.polls,
+            busy: stats.busy,
+            longest_poll: stats.longest_poll,
+            slow_polls: stats.slow_polls,
+            queued: self.queued.load(Ordering::Acquire),
+            priority: self.priority,
+        }
+    }
+
+    /// Records one poll of the task, `elapsed` is only known when
+    /// instrumentation is enabled.
+    fn record_poll(
+        &self,
+        done: bool,
+        elapsed: Option<Duration>,
+        instrumentation: Option<Instrumentation>,
+    ) {
+        let mut stats = self.stats.borrow_mut();
+        stats.done = done;
+        stats.polls += 1;
+        let elapsed = match elapsed {
+            Some(elapsed) => elapsed,
+            None => return,
+        };
+        stats.busy += elapsed;
+        if elapsed > stats.longest_poll {
+            stats.longest_poll = elapsed;
+        }
+        if let Some(threshold) = instrumentation.and_then(|i| i.slow_poll_threshold) {
+            if elapsed > threshold {
+                stats.slow_polls += 1;
+                log::log(
+                    LogLevel::Warn,
+                    file!(),
+                    line!(),
+                    module_path!(),
+                    &format!(
+                        "Task {} ({}) took {:?} to poll, more than {:?}",
+                        self.id,
+                        self.name.as_ref().map(String::as_str).unwrap_or("unnamed"),
+                        elapsed,
+                        threshold
+                    ),
+                );
+            }
+        }
+    }
+
+    fn is_home(&self) -> bool {
+        std::thread::current().id() == self.home
+    }
+
+    /// Adds the task to the executor of the current thread, which must be its home.
+    fn enqueue(&self) {
+        CURRENT_EXECUTOR.with(|current| {
+            if let Some(ref current_thread) = *current.borrow() {
+                let self_clone = clone_task_handle(self);
+                current_thread.tq.borrow_mut().add_task(self_clone);
+            }
+        });
+    }
+}
+
+// The reference count and the `queued` flag are atomic, so wakers can be sent
+// to other threads. The task itself is only ever touched on its home thread:
+// wake-ups from other threads are forwarded with spdk_thread_send_msg, and so
+// is the task if the last reference goes away elsewhere. Without a home SPDK
+// thread, both are given up on, see `stranded_tasks`.
 unsafe impl Send for TaskHandle {}
 unsafe impl Sync for TaskHandle {}
 
-unsafe impl UnsafeWake for TaskHandle {
-    unsafe fn clone_raw(&self) -> Waker {
-        Waker::new(clone_task_handle_ptr(self))
-    }
+impl TaskHandle {
+    fn wake_by_ref(&self) {
+        if self.queued.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        if self.is_home() {
+            self.enqueue();
+            return;
+        }
 
-    unsafe fn drop_raw(&self) {
-        // This will drop the Rc
-        let _ = Rc::from_raw(self);
+        match self.home_thread {
+            Some(ref home_thread) => {
+                let self_clone = clone_task_handle(self);
+                thread::send_msg(home_thread, move || self_clone.enqueue())
+            }
+            None => {
+                // Let a later wake-up from the home thread queue the task.
+                self.queued.store(false, Ordering::Release);
+                STRANDED_TASKS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
     }
+}
 
-    unsafe fn wake(&self) {
-        if !self.queued.replace(true) {
-            CURRENT_EXECUTOR.with(|current| {
-                if let Some(ref current_thread) = *current.borrow() {
-                    let self_clone = clone_task_handle(self);
-                    current_thread.tq.borrow_mut().add_task(self_clone);
-                }
-            });
+/// Wakers point at a `TaskHandle` and own one reference to it.
+static TASK_WAKER_VTABLE: RawWakerVTable =
+    RawWakerVTable::new(clone_raw, wake_raw, wake_by_ref_raw, drop_raw);
+
+/// Creates a `Waker` that owns the given reference to the task.
+fn task_waker(task_handle: Arc<TaskHandle>) -> Waker {
+    unsafe { Waker::from_raw(raw_waker(task_handle)) }
+}
+
+fn raw_waker(task_handle: Arc<TaskHandle>) -> RawWaker {
+    RawWaker::new(Arc::into_raw(task_handle) as *const (), &TASK_WAKER_VTABLE)
+}
+
+unsafe fn clone_raw(data: *const ()) -> RawWaker {
+    raw_waker(clone_task_handle(&*(data as *const TaskHandle)))
+}
+
+unsafe fn wake_raw(data: *const ()) {
+    // This will drop the Arc once woken up
+    let task_handle = Arc::from_raw(data as *const TaskHandle);
+    task_handle.wake_by_ref();
+}
+
+unsafe fn wake_by_ref_raw(data: *const ()) {
+    (*(data as *const TaskHandle)).wake_by_ref();
+}
+
+unsafe fn drop_raw(data: *const ()) {
+    // This will drop the Arc
+    let _ = Arc::from_raw(data as *const TaskHandle);
+}
+
+impl Drop for TaskHandle {
+    fn drop(&mut self) {
+        let task = match self.task.get_mut().take() {
+            Some(task) => task,
+            None => return,
+        };
+
+        if self.is_home() {
+            drop(task);
+            return;
+        }
+        match self.home_thread {
+            Some(ref home_thread) => {
+                let task = SendTask(task);
+                thread::send_msg(home_thread, move || drop(task.0));
+            }
+            None => {
+                // The future is not Send, dropping it here would be unsound.
+                mem::forget(task);
+                STRANDED_TASKS.fetch_add(1, Ordering::Relaxed);
+            }
         }
     }
 }
 
+/// Moves a task back to its home thread to be dropped there.
+struct SendTask(TaskContext);
+
+unsafe impl Send for SendTask {}
+
 struct TaskContext {
     fut: LocalFutureObj<'static, ()>,
 }
@@ -135,10 +530,15 @@ impl TaskContext {
     }
 }
 
-pub struct Enter {}
+pub struct Enter {
+    poller: Option<PollerHandle>,
+}
 
 impl Drop for Enter {
     fn drop(&mut self) {
+        // Unregister the poller first, so it never runs without an executor.
+        drop(self.poller.take());
+
         CURRENT_EXECUTOR.with(|current| {
             if current.borrow().as_ref().is_none() {
                 panic!("Executor not initialized")
@@ -162,32 +562,406 @@ pub fn initialize() -> Enter {
 
         match current.replace(Some(executor)) {
             Some(_) => panic!("Executor already initialized"),
-            _ => Enter {},
+            _ => Enter { poller: None },
         }
     })
 }
 
-pub fn spawn<F>(future: F)
+/// Initializes the executor and registers a poller on the current SPDK thread
+/// that drives it. The poller is unregistered when the returned guard drops.
+pub fn install() -> Enter {
+    let mut enter = initialize();
+    enter.poller = Some(poller_register(pure_poll));
+    enter
+}
+
+/// Starts the SPDK application, runs the future returned by `async_main` on
+/// the first reactor and stops the application once it completes. The
+/// application exits successfully if and only if the future returns `Ok`.
+pub fn block_on_app<F, Fut, T>(opts: SpdkAppOpts, async_main: F) -> Result<T, Error>
 where
-    F: Future<Output = ()> + 'static,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T, Error>> + 'static,
+    T: 'static,
+{
+    let result: Rc<RefCell<Option<Result<T, Error>>>> = Rc::new(RefCell::new(None));
+    let app_result = result.clone();
+    let mut async_main = Some(async_main);
+
+    let started = opts.start(move || {
+        let async_main = async_main.take().expect("Application started twice");
+        let result = app_result.clone();
+        run_main(async_main(), move |res| *result.borrow_mut() = Some(res));
+    });
+
+    APP_ENTER.with(|slot| {
+        if let Some(mut enter) = slot.borrow_mut().take() {
+            // The app was stopped from elsewhere and the reactor's thread is
+            // already gone, so its pollers cannot be unregistered anymore.
+            mem::forget(enter.poller.take());
+        }
+    });
+
+    let res = result.borrow_mut().take();
+    match res {
+        Some(res) => res,
+        None => {
+            started?;
+            Err(ExecutorError::AppStopped())?
+        }
+    }
+}
+
+/// Starts the SPDK application and runs `async_main` like `block_on_app`, but
+/// with a graceful shutdown: on SIGINT, SIGTERM or `event::start_shutdown`,
+/// `event::shutdown_signal` resolves and the hooks registered with
+/// `event::on_shutdown` run. If `async_main` has not completed by the end of
+/// the grace period, the application is stopped anyway and fails with
+/// `ExecutorError::GracePeriodExpired`.
+///
+/// Returns the exit code of the process: 0 if `async_main` returned `Ok`,
+/// 1 otherwise. Any `shutdown_cb` set on `opts` is replaced.
+pub fn start_async<F, Fut>(mut opts: SpdkAppOpts, async_main: F) -> i32
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<(), Error>> + 'static,
+{
+    let grace_period = opts.grace_period.unwrap_or(event::DEFAULT_GRACE_PERIOD);
+    opts.shutdown_cb(move || event::begin_shutdown(grace_period));
+
+    let res = block_on_app(opts, async_main);
+    let res = match res {
+        Err(_) if event::grace_period_expired() => Err(ExecutorError::GracePeriodExpired().into()),
+        res => res,
+    };
+    event::reset_shutdown();
+
+    match res {
+        Ok(()) => 0,
+        Err(e) => {
+            log::log(
+                LogLevel::Error,
+                file!(),
+                line!(),
+                module_path!(),
+                &format!("Application failed: {}", e),
+            );
+            1
+        }
+    }
+}
+
+/// Runs `future` on the executor of the current reactor, installing one if
+/// needed, then hands its outcome to `on_done` and stops the application.
+/// A panic in `future` is reported as an error.
+pub(crate) fn run_main<Fut, T, D>(future: Fut, on_done: D)
+where
+    Fut: Future<Output = Result<T, Error>> + 'static,
+    T: 'static,
+    D: FnOnce(Result<T, Error>) + 'static,
 {
-    let task = Rc::new(TaskHandle {
-        task: UnsafeCell::new(Some(TaskContext::new(future))),
-        queued: Cell::new(true),
+    let installed = CURRENT_EXECUTOR.with(|current| current.borrow().is_some());
+    if !installed {
+        APP_ENTER.with(|slot| *slot.borrow_mut() = Some(install()));
+    }
+
+    spawn(async move {
+        let res = match AssertUnwindSafe(future).catch_unwind().await {
+            Ok(res) => res,
+            Err(panic) => Err(ExecutorError::Panicked(panic_message(&panic)).into()),
+        };
+        let success = res.is_ok();
+        on_done(res);
+        stop_app(success);
+    });
+}
+
+/// Extracts the message of a panic payload.
+pub(crate) fn panic_message(panic: &Box<dyn Any + Send>) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<Any>".to_string()
+    }
+}
+
+/// Releases the executor installed by `block_on_app` and stops the app.
+/// This cannot be done from within `pure_poll`, so it is deferred to an event.
+fn stop_app(success: bool) {
+    let deferred = env::current_core().map(|core| {
+        event::send_on(core, move || {
+            APP_ENTER.with(|slot| drop(slot.borrow_mut().take()));
+            event::app_stop(success);
+        })
     });
+    match deferred {
+        Some(Ok(())) => {}
+        _ => event::app_stop(success),
+    }
+}
+
+/// Spawns `future` on the executor of the current thread.
+///
+/// The returned handle resolves with the output of the future, or with an
+/// error if the task panicked or was aborted. Dropping it detaches the task.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    Builder::new().spawn(future)
+}
+
+/// Like `spawn`, but the task shows up under `name` in `tasks()` and in
+/// slow poll warnings.
+pub fn spawn_named<S, F>(name: S, future: F) -> JoinHandle<F::Output>
+where
+    S: Into<String>,
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    Builder::new().name(name).spawn(future)
+}
+
+/// Spawns tasks on the executor of the current thread with a name or a
+/// priority, see `spawn`.
+#[derive(Clone, Debug, Default)]
+pub struct Builder {
+    name: Option<String>,
+    priority: Priority,
+}
 
-    with_queue(|queue| queue.add_task(task))
+impl Builder {
+    pub fn new() -> Builder {
+        Builder::default()
+    }
+
+    pub fn name<S: Into<String>>(mut self, name: S) -> Builder {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn priority(mut self, priority: Priority) -> Builder {
+        self.priority = priority;
+        self
+    }
+
+    pub fn spawn<F>(self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        let (joinable, handle) = joinable(future);
+
+        with_default(|executor| executor.spawn_joinable(joinable, self.name, self.priority));
+        handle
+    }
 }
 
+struct AbortState {
+    aborted: AtomicBool,
+    /// Task running the `Joinable`, once spawned. Weak, so that the handle
+    /// does not keep the task alive.
+    task: Mutex<Weak<TaskHandle>>,
+}
+
+/// Handle to a spawned task, see `spawn`.
+pub struct JoinHandle<T> {
+    receiver: oneshot::Receiver<Result<T, JoinError>>,
+    abort: Arc<AbortState>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Cancels the task: it is dropped instead of being polled again, and the
+    /// handle resolves with `JoinError::Cancelled`. Can be called from any thread.
+    pub fn abort(&self) {
+        self.abort.aborted.store(true, Ordering::Release);
+        let task = self.abort.task.lock().unwrap().upgrade();
+        if let Some(task) = task {
+            task.wake_by_ref();
+        }
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = Pin::get_mut(self);
+        match Pin::new(&mut this.receiver).poll(cx) {
+            Poll::Ready(Ok(res)) => Poll::Ready(res),
+            // The task was dropped without completing, e.g. with its executor.
+            Poll::Ready(Err(_)) => Poll::Ready(Err(JoinError::Cancelled())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Wraps a spawned future: catches its panics, honours `JoinHandle::abort`
+/// and reports its output to the `JoinHandle`.
+struct Joinable<F: Future> {
+    future: Option<F>,
+    sender: Option<oneshot::Sender<Result<F::Output, JoinError>>>,
+    abort: Arc<AbortState>,
+}
+
+fn joinable<F: Future>(future: F) -> (Joinable<F>, JoinHandle<F::Output>) {
+    let (sender, receiver) = oneshot::channel();
+    let abort = Arc::new(AbortState {
+        aborted: AtomicBool::new(false),
+        task: Mutex::new(Weak::new()),
+    });
+    let joinable = Joinable {
+        future: Some(future),
+        sender: Some(sender),
+        abort: abort.clone(),
+    };
+    (joinable, JoinHandle { receiver, abort })
+}
+
+impl<F: Future> Joinable<F> {
+    fn complete(&mut self, res: Result<F::Output, JoinError>) -> Poll<()> {
+        // Dropped in place, which is fine for a pinned value.
+        self.future = None;
+        if let Some(sender) = self.sender.take() {
+            // The handle may have been dropped already.
+            let _ = sender.send(res);
+        }
+        Poll::Ready(())
+    }
+}
+
+impl<F: Future> Future for Joinable<F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // The future is never moved out of `self.future`.
+        let this = unsafe { Pin::get_unchecked_mut(self) };
+
+        if this.abort.aborted.load(Ordering::Acquire) {
+            return this.complete(Err(JoinError::Cancelled()));
+        }
+
+        let future = match this.future.as_mut() {
+            Some(future) => unsafe { Pin::new_unchecked(future) },
+            None => return Poll::Ready(()),
+        };
+        match panic::catch_unwind(AssertUnwindSafe(|| future.poll(cx))) {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(output)) => this.complete(Ok(output)),
+            Err(panic) => this.complete(Err(JoinError::Panicked(panic_message(&panic)))),
+        }
+    }
+}
+
+/// Where `spawn_on` runs a future.
+#[derive(Clone)]
+pub enum Target {
+    /// The reactor of the given core.
+    Core(u32),
+    /// The given SPDK thread.
+    Thread(SpdkThread),
+}
+
+impl From<u32> for Target {
+    fn from(core: u32) -> Target {
+        Target::Core(core)
+    }
+}
+
+impl From<SpdkThread> for Target {
+    fn from(thread: SpdkThread) -> Target {
+        Target::Thread(thread)
+    }
+}
+
+/// Spawns `future` on the executor of another reactor or SPDK thread. The
+/// future is moved there with an event or spdk_thread_send_msg, and is polled
+/// and woken up on that thread only.
+///
+/// The target must have an executor installed, see `install` and
+/// `install_on_all_cores`; otherwise the future is dropped and the handle
+/// resolves with `JoinError::Cancelled`.
+pub fn spawn_on<T, F>(target: T, future: F) -> Result<JoinHandle<F::Output>, Error>
+where
+    T: Into<Target>,
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let (joinable, handle) = joinable(future);
+    match target.into() {
+        Target::Core(core) => event::send_on(core, move || spawn_here(joinable))?,
+        Target::Thread(thread) => thread::send_msg(&thread, move || spawn_here(joinable)),
+    }
+    Ok(handle)
+}
+
+/// Spawns `joinable` on the executor of the current thread, if any. Otherwise
+/// it is dropped and its handle resolves with `JoinError::Cancelled`.
+fn spawn_here<F>(joinable: Joinable<F>)
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    CURRENT_EXECUTOR.with(|current| {
+        if let Some(ref executor) = *current.borrow() {
+            executor.spawn_joinable(joinable, None, Priority::Normal);
+        }
+    })
+}
+
+/// Spawns `future` on the executor of the current thread, if any. Otherwise
+/// it is dropped. Its panics are caught, as for any other task.
+pub(crate) fn spawn_remote<F>(future: F)
+where
+    F: Future<Output = ()> + 'static,
+{
+    let (joinable, _) = joinable(future);
+    spawn_here(joinable)
+}
+
+/// Installs an executor, driven by a poller, on the reactor of every core.
+/// Cores that already have one are left alone.
+pub fn install_on_all_cores() -> Result<(), Error> {
+    for core in env::cores() {
+        event::send_on(core, || {
+            let installed = CURRENT_EXECUTOR.with(|current| current.borrow().is_some());
+            if !installed {
+                APP_ENTER.with(|slot| *slot.borrow_mut() = Some(install()));
+            }
+        })?;
+    }
+    Ok(())
+}
+
+/// Releases the executors installed by `install_on_all_cores`. Tasks that
+/// are still pending on those cores are dropped.
+pub fn uninstall_on_all_cores() -> Result<(), Error> {
+    for core in env::cores() {
+        event::send_on(core, || {
+            APP_ENTER.with(|slot| drop(slot.borrow_mut().take()))
+        })?;
+    }
+    Ok(())
+}
+
+/// Polls the queued tasks of the current executor, up to the budget set with
+/// `set_poll_budget`. Returns whether any task was polled.
 pub fn pure_poll() -> bool {
     with_default(|executor| {
+        let budget = executor.budget.get();
+        let mut polled = 0;
         let mut ret = false;
         loop {
-            let task_handle_ptr = match executor.tq.borrow_mut().poll_task_from_rc() {
+            if budget.map_or(false, |budget| polled >= budget.get()) {
+                break;
+            }
+
+            let task_handle_ptr = match executor.tq.borrow_mut().poll_task_from_arc() {
                 Some(rc_task_handle) => rc_task_handle,
-                None => {
-                    return ret;
-                }
+                None => break,
             };
 
             unsafe {
@@ -196,7 +970,7 @@ pub fn pure_poll() -> bool {
                     None => {
                         // The future has gone away; just need to make sure
                         // we invoke Drop on task_handle_ptr
-                        let _node = Rc::from_raw(task_handle_ptr);
+                        let _node = Arc::from_raw(task_handle_ptr);
                         continue;
                     }
                 };
@@ -205,39 +979,43 @@ pub fn pure_poll() -> bool {
                 // polling. This ensures that the future gets
                 // rescheduled if it is notified **during** a call
                 // to `pure_poll`.
-                let prev = (*task_handle_ptr).queued.replace(false);
+                let prev = (*task_handle_ptr).queued.swap(false, Ordering::AcqRel);
                 assert!(prev);
 
                 ret = true;
+                polled += 1;
 
                 struct Bomb {
-                    task_handle: Option<Rc<TaskHandle>>,
+                    task_handle: Option<Arc<TaskHandle>>,
                 }
 
                 // Bomb now owns task_handle_ptr
                 let mut bomb = Bomb {
-                    task_handle: Some(Rc::from_raw(task_handle_ptr)),
+                    task_handle: Some(Arc::from_raw(task_handle_ptr)),
                 };
 
+                let instrumentation = executor.instrumentation.get();
+                let start = instrumentation.map(|_| TickInstant::now());
+
                 let res = {
-                    // We need to increase the counter temporarily,
-                    // so that the waker does not drop the task still owned by Bomb
-                    // when it drops;
-                    // TODO: probably can do away with this clone.
-                    let clone_task_handle_ptr =
-                        Rc::into_raw(bomb.task_handle.as_ref().unwrap().clone());
-                    // TODO: I think I should be able to use some of the functions from below
-                    let lw = LocalWaker::new(NonNull::new_unchecked(
-                        clone_task_handle_ptr as *mut TaskHandle,
-                    ));
-                    // TODO: need the executor bit done
+                    // The waker gets its own reference to the task,
+                    // so that dropping it does not drop the task still owned by Bomb.
+                    let waker = task_waker(bomb.task_handle.as_ref().unwrap().clone());
+                    let mut cx = Context::from_waker(&waker);
                     let future = Pin::new(&mut task.fut);
-                    future.poll(&lw)
+                    future.poll(&mut cx)
                 };
 
+                let elapsed = start.map(TickInstant::elapsed);
+                bomb.task_handle.as_ref().unwrap().record_poll(
+                    res.is_ready(),
+                    elapsed,
+                    instrumentation,
+                );
+
                 if let Poll::Pending = res {
-                    // We have transferred the reference to the task to Waker
-                    // So need to move it out of Bomb and put back the task
+                    // Wakers that were kept around hold their own references,
+                    // so move ours out of Bomb and put back the task
                     let task_handle = bomb.task_handle.take().unwrap();
 
                     *task_handle.task.get() = Some(task);
@@ -247,6 +1025,14 @@ pub fn pure_poll() -> bool {
                 }
             }
         }
+
+        // Tasks that yielded go back to the queue only now, so that the
+        // other pollers of the reactor get to run before they are polled again.
+        let yielded = executor.yielded.replace(Vec::new());
+        for waker in yielded {
+            waker.wake();
+        }
+        ret
     })
 }
 
@@ -270,49 +1056,34 @@ where
     })
 }
 
-fn with_queue<F, R>(f: F) -> R
-where
-    F: FnOnce(&mut TaskQueue) -> R,
-{
-    with_default(|executor| f(&mut executor.tq.borrow_mut()))
-}
-
-fn clone_task_handle(task_handle: &TaskHandle) -> Rc<TaskHandle> {
-    let self_as_rc = unsafe { Rc::from_raw(task_handle) };
-    let self_clone = self_as_rc.clone();
+fn clone_task_handle(task_handle: &TaskHandle) -> Arc<TaskHandle> {
+    let self_as_arc = unsafe { Arc::from_raw(task_handle) };
+    let self_clone = self_as_arc.clone();
 
-    // We need to make sure self_as_rc does not drop,
+    // We need to make sure self_as_arc does not drop,
     // since it is STILL referenced by this TaskHandle
-    forget_rc(self_as_rc);
+    forget_arc(self_as_arc);
 
     self_clone
 }
 
-fn clone_task_handle_ptr(task_handle: &TaskHandle) -> NonNull<TaskHandle> {
-    // TODO: should be fine right?
-    unsafe {
-        NonNull::new_unchecked(Rc::into_raw(clone_task_handle(task_handle)) as *mut TaskHandle)
-    }
-}
-
-fn forget_rc(task_handle: Rc<TaskHandle>) {
-    let _ = Rc::into_raw(task_handle);
+fn forget_arc(task_handle: Arc<TaskHandle>) {
+    let _ = Arc::into_raw(task_handle);
 }
 
 #[cfg(test)]
 mod tests {
 
     use super::*;
-    use futures::task::Poll;
     use hamcrest2::prelude::*;
 
-    type PollerFn = dyn Fn(&LocalWaker, &mut Controller) -> Poll<()>;
+    type PollerFn = dyn Fn(&mut Context<'_>, &mut Controller) -> Poll<()>;
 
     struct Controller {
         pollers: VecDeque<Box<PollerFn>>,
         poll_count: usize,
         dropped: bool,
-        waker: Option<LocalWaker>,
+        waker: Option<Waker>,
     }
 
     impl Controller {
@@ -325,14 +1096,14 @@ mod tests {
             }
         }
 
-        fn save_waker(&mut self, waker: &LocalWaker) {
+        fn save_waker(&mut self, waker: &Waker) {
             match self.waker {
                 Some(_) => panic!("Waker already saved"),
                 None => self.waker = Some(waker.clone()),
             }
         }
 
-        fn unwrap_waker(&mut self) -> LocalWaker {
+        fn unwrap_waker(&mut self) -> Waker {
             self.waker.take().unwrap()
         }
 
@@ -355,7 +1126,7 @@ mod tests {
 
         fn push_pollers<P>(&mut self, poller: P)
         where
-            P: Fn(&LocalWaker, &mut Controller) -> Poll<()> + 'static,
+            P: Fn(&mut Context<'_>, &mut Controller) -> Poll<()> + 'static,
         {
             self.pollers.push_back(Box::new(poller))
         }
@@ -384,14 +1155,14 @@ mod tests {
     impl Future for MockFuture {
         type Output = ();
 
-        fn poll(self: Pin<&mut Self>, lw: &LocalWaker) -> Poll<()> {
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
             let mut ctrl = self.controller.borrow_mut();
             let poller = match ctrl.pop_pollers() {
                 Some(poller) => poller,
                 None => panic!("Called poll when not expected"),
             };
             ctrl.poll();
-            poller(lw, &mut ctrl)
+            poller(cx, &mut ctrl)
         }
     }
 
@@ -409,8 +1180,8 @@ mod tests {
     fn future_is_notified_from_outside_poll() {
         mock_test(|ctrl| {
             // Save waker
-            ctrl.borrow_mut().push_pollers(|lw, ctrl| {
-                ctrl.save_waker(lw);
+            ctrl.borrow_mut().push_pollers(|cx, ctrl| {
+                ctrl.save_waker(cx.waker());
                 Poll::Pending
             });
 
@@ -418,8 +1189,8 @@ mod tests {
 
             // Notify future multiple times
             let waker = ctrl.borrow_mut().unwrap_waker();
-            waker.wake();
-            waker.wake();
+            waker.wake_by_ref();
+            waker.wake_by_ref();
 
             // Do not save the waker this time
             ctrl.borrow_mut().push_pollers(|_, _| Poll::Pending);
@@ -435,10 +1206,10 @@ mod tests {
     #[test]
     fn future_is_polled_again_if_notified_from_poll() {
         mock_test(|ctrl| {
-            ctrl.borrow_mut().push_pollers(|lw, _| {
+            ctrl.borrow_mut().push_pollers(|cx, _| {
                 // Wake multiple times
-                lw.wake();
-                lw.wake();
+                cx.waker().wake_by_ref();
+                cx.waker().wake_by_ref();
 
                 Poll::Pending
             });
@@ -449,7 +1220,209 @@ mod tests {
         })
     }
 
-    // TODO: Spawn a future that spawns from poll.
+    #[test]
+    fn join_handle_resolves_with_output() {
+        join_test(async { 42 }, |res| {
+            assert_that!(res.unwrap(), is(equal_to(42)));
+        })
+    }
+
+    #[test]
+    fn panic_is_reported_through_join_handle() {
+        join_test(
+            async {
+                panic!("boom");
+            },
+            |res: Result<(), JoinError>| match res {
+                Err(JoinError::Panicked(message)) => assert_that!(message, is(equal_to("boom"))),
+                _ => panic!("Expected a panic"),
+            },
+        )
+    }
+
+    #[test]
+    fn aborted_task_is_dropped() {
+        let _enter = initialize();
+
+        let ctrl = Rc::new(RefCell::new(Controller::new()));
+        ctrl.borrow_mut().push_pollers(|cx, ctrl| {
+            ctrl.save_waker(cx.waker());
+            Poll::Pending
+        });
+        let handle = spawn(MockFuture::new(ctrl.clone()));
+
+        assert_pure_poll(&ctrl, 1, false);
+
+        // Dropped without being polled again
+        handle.abort();
+        assert_pure_poll(&ctrl, 1, true);
+    }
+
+    #[test]
+    fn task_without_home_spdk_thread_is_stranded_off_home() {
+        let _enter = initialize();
+
+        let waker: Arc<Mutex<Option<Waker>>> = Arc::new(Mutex::new(None));
+        let waker_clone = waker.clone();
+        spawn(futures::future::poll_fn(move |cx| {
+            *waker_clone.lock().unwrap() = Some(cx.waker().clone());
+            Poll::<()>::Pending
+        }));
+        pure_poll();
+
+        // Waking and releasing the last reference elsewhere neither panics
+        // nor drops the task there
+        let waker = waker.lock().unwrap().take().unwrap();
+        let stranded = stranded_tasks();
+        std::thread::spawn(move || {
+            waker.wake_by_ref();
+            drop(waker);
+        })
+        .join()
+        .unwrap();
+        assert_that!(stranded_tasks() - stranded, is(equal_to(2)));
+        assert_that!(pure_poll(), is(equal_to(false)));
+    }
+
+    #[test]
+    fn join_handle_does_not_keep_the_task_alive() {
+        let _enter = initialize();
+
+        let ctrl = Rc::new(RefCell::new(Controller::new()));
+        ctrl.borrow_mut().push_pollers(|_, _| Poll::Pending);
+        let handle = spawn(MockFuture::new(ctrl.clone()));
+
+        // Nothing can wake the task up anymore
+        assert_pure_poll(&ctrl, 1, true);
+        handle.abort();
+        assert_pure_poll(&ctrl, 1, true);
+    }
+
+    #[test]
+    fn future_spawned_from_poll_is_polled() {
+        mock_test(|ctrl| {
+            let inner = Rc::new(RefCell::new(Controller::new()));
+            inner.borrow_mut().push_pollers(|_, _| Poll::Ready(()));
+
+            let inner_clone = inner.clone();
+            ctrl.borrow_mut().push_pollers(move |_, _| {
+                spawn(MockFuture::new(inner_clone.clone()));
+                Poll::Ready(())
+            });
+
+            // Both futures complete within the same call
+            assert_pure_poll(&ctrl, 1, true);
+            assert_pure_poll(&inner, 1, true);
+        })
+    }
+
+    #[test]
+    fn waking_a_completed_future_does_nothing() {
+        mock_test(|ctrl| {
+            ctrl.borrow_mut().push_pollers(|cx, ctrl| {
+                ctrl.save_waker(cx.waker());
+                Poll::Ready(())
+            });
+
+            assert_pure_poll(&ctrl, 1, true);
+
+            let waker = ctrl.borrow_mut().unwrap_waker();
+            waker.wake();
+
+            assert_pure_poll(&ctrl, 1, true);
+        })
+    }
+
+    #[test]
+    fn live_tasks_are_reported_until_they_complete() {
+        let _enter = initialize();
+
+        let ctrl = Rc::new(RefCell::new(Controller::new()));
+        ctrl.borrow_mut().push_pollers(|cx, ctrl| {
+            ctrl.save_waker(cx.waker());
+            Poll::Pending
+        });
+        ctrl.borrow_mut().push_pollers(|_, _| Poll::Ready(()));
+        let _handle = spawn_named("mock", MockFuture::new(ctrl.clone()));
+
+        assert_pure_poll(&ctrl, 1, false);
+
+        let snapshot = tasks();
+        assert_that!(snapshot.len(), is(equal_to(1)));
+        assert_that!(
+            snapshot[0].name.clone(),
+            is(equal_to(Some("mock".to_string())))
+        );
+        assert_that!(snapshot[0].polls, is(equal_to(1)));
+        assert_that!(snapshot[0].queued, is(false));
+
+        ctrl.borrow_mut().unwrap_waker().wake();
+        assert_pure_poll(&ctrl, 2, true);
+
+        assert_that!(tasks().len(), is(equal_to(0)));
+    }
+
+    #[test]
+    fn high_priority_tasks_are_polled_first_within_budget() {
+        let _enter = initialize();
+        set_poll_budget(NonZeroUsize::new(1));
+
+        let normal = Rc::new(RefCell::new(Controller::new()));
+        normal.borrow_mut().push_pollers(|_, _| Poll::Ready(()));
+        let high = Rc::new(RefCell::new(Controller::new()));
+        high.borrow_mut().push_pollers(|_, _| Poll::Ready(()));
+
+        spawn(MockFuture::new(normal.clone()));
+        Builder::new()
+            .priority(Priority::High)
+            .spawn(MockFuture::new(high.clone()));
+
+        assert_pure_poll(&high, 1, true);
+        assert_that!(normal.borrow().poll_count(), is(equal_to(0)));
+
+        assert_pure_poll(&normal, 1, true);
+        assert_that!(pure_poll(), is(false));
+    }
+
+    #[test]
+    fn yielding_task_is_polled_on_next_call() {
+        let _enter = initialize();
+
+        let polls = Rc::new(Cell::new(0));
+        let task_polls = polls.clone();
+        spawn(async move {
+            loop {
+                task_polls.set(task_polls.get() + 1);
+                yield_now().await;
+            }
+        });
+
+        assert_that!(pure_poll(), is(true));
+        assert_that!(polls.get(), is(equal_to(1)));
+        assert_that!(pure_poll(), is(true));
+        assert_that!(polls.get(), is(equal_to(2)));
+    }
+
+    fn join_test<F, C>(future: F, check: C)
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+        C: FnOnce(Result<F::Output, JoinError>),
+    {
+        let _enter = initialize();
+
+        let handle = spawn(future);
+        let result = Rc::new(RefCell::new(None));
+        let result_clone = result.clone();
+        spawn(async move {
+            *result_clone.borrow_mut() = Some(handle.await);
+        });
+
+        pure_poll();
+
+        let res = result.borrow_mut().take();
+        check(res.expect("Task did not complete"))
+    }
 
     fn mock_test<F>(f: F)
     where
@@ -1,7 +1,8 @@
 use libc::c_int;
 use std::ffi::c_void;
+use std::time::Duration;
 
-use spdk:{spdk_poller, spdk_poller_register, spdk_poller_unregister};
+use spdk::{spdk_poller, spdk_poller_register, spdk_poller_unregister};
 
 pub struct PollerHandle {
     pub(crate) poller: *mut spdk_poller,
@@ -12,16 +13,33 @@ pub struct PollerHandle {
 impl Drop for PollerHandle {
     #[allow(clippy::cast_ptr_alignment)]
     fn drop(&mut self) {
-        let tmp_poller = self.poller;
-        // This is rather dogdy, spdk_poller_unregister will write NULL to self.poller,
-        // hopefully that isn't going to crash!
-        unsafe { spdk_poller_unregister(tmp_poller as *mut *mut spdk_poller) }
+        // spdk_poller_unregister will write NULL to the pointer it is given,
+        // so hand it a copy rather than self.poller.
+        let mut tmp_poller = self.poller;
+        unsafe { spdk_poller_unregister(&mut tmp_poller as *mut *mut spdk_poller) }
     }
 }
 
 /// Registers a poller with spdk.
 /// f: should return true if any work was done
 pub fn poller_register<F>(f: F) -> PollerHandle
+where
+    F: Fn() -> bool + 'static,
+{
+    register(f, 0)
+}
+
+/// Registers a poller with spdk that runs at most once every `period`.
+/// f: should return true if any work was done
+pub fn poller_register_timed<F>(f: F, period: Duration) -> PollerHandle
+where
+    F: Fn() -> bool + 'static,
+{
+    let period_us = period.as_secs() * 1_000_000 + u64::from(period.subsec_micros());
+    register(f, period_us)
+}
+
+fn register<F>(f: F, period_us: u64) -> PollerHandle
 where
     F: Fn() -> bool + 'static,
 {
@@ -40,7 +58,7 @@ where
 
     let f_raw = Box::into_raw(Box::new(f)) as *mut dyn Fn() -> bool;
     let f_pointer = f_raw as *const _ as *mut c_void;
-    let poller = unsafe { spdk_poller_register(Some(poller_wrapper::<F>), f_pointer, 0) };
+    let poller = unsafe { spdk_poller_register(Some(poller_wrapper::<F>), f_pointer, period_us) };
     PollerHandle {
         // TODO: handle failure
         poller,
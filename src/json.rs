@@ -0,0 +1,113 @@
+//! Collects the output of SPDK's JSON writer into a `serde_json::Value`.
+
+use spdk;
+
+use std::ffi::c_void;
+use std::slice;
+
+use failure::Error;
+use serde_json::Value;
+
+#[derive(Debug, Fail)]
+pub enum JsonError {
+    #[fail(display = "Could not start writing JSON")]
+    BeginError(),
+
+    #[fail(display = "Could not finish writing JSON: {}", _0)]
+    EndError(i32),
+
+    #[fail(display = "SPDK wrote invalid JSON: {}", _0)]
+    ParseError(String),
+}
+
+/// spdk_json_write_begin() / spdk_json_write_end()
+///
+/// Runs `f` with a write context whose output is returned as a string.
+pub(crate) fn write_json<F>(f: F) -> Result<String, Error>
+where
+    F: FnOnce(*mut spdk::spdk_json_write_ctx),
+{
+    extern "C" fn write_cb(ctx: *mut c_void, data: *const c_void, size: usize) -> i32 {
+        let out = unsafe { &mut *(ctx as *mut Vec<u8>) };
+        out.extend_from_slice(unsafe { slice::from_raw_parts(data as *const u8, size) });
+        0
+    }
+
+    let mut out: Vec<u8> = Vec::new();
+    let w = unsafe {
+        spdk::spdk_json_write_begin(Some(write_cb), &mut out as *mut _ as *mut c_void, 0)
+    };
+    if w.is_null() {
+        return Err(JsonError::BeginError())?;
+    }
+
+    f(w);
+
+    let rc = unsafe { spdk::spdk_json_write_end(w) };
+    if rc != 0 {
+        return Err(JsonError::EndError(rc))?;
+    }
+    Ok(String::from_utf8_lossy(&out).into_owned())
+}
+
+/// Like `write_json`, but parses the output.
+pub(crate) fn write_json_value<F>(f: F) -> Result<Value, Error>
+where
+    F: FnOnce(*mut spdk::spdk_json_write_ctx),
+{
+    let text = write_json(f)?;
+    match serde_json::from_str(&text) {
+        Ok(value) => Ok(value),
+        Err(e) => Err(JsonError::ParseError(e.to_string()))?,
+    }
+}
+
+/// spdk_json_write_array_begin() / spdk_json_write_array_end()
+///
+/// Like `write_json_value`, for functions such as the `*_write_config_json`
+/// ones that write a series of values and leave the enclosing array to the
+/// caller.
+pub(crate) fn write_json_array<F>(f: F) -> Result<Value, Error>
+where
+    F: FnOnce(*mut spdk::spdk_json_write_ctx),
+{
+    write_json_value(|w| unsafe {
+        spdk::spdk_json_write_array_begin(w);
+        f(w);
+        spdk::spdk_json_write_array_end(w);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use hamcrest2::prelude::*;
+    use serde_json::json;
+    use std::ffi::CString;
+
+    #[test]
+    fn loose_values_are_collected_into_an_array() {
+        let methods = vec![
+            CString::new("nvmf_create_transport").unwrap(),
+            CString::new("nvmf_subsystem_create").unwrap(),
+        ];
+        let method = CString::new("method").unwrap();
+
+        let value = write_json_array(|w| unsafe {
+            for name in &methods {
+                spdk::spdk_json_write_object_begin(w);
+                spdk::spdk_json_write_named_string(w, method.as_ptr(), name.as_ptr());
+                spdk::spdk_json_write_object_end(w);
+            }
+        });
+
+        assert_that!(
+            value.unwrap(),
+            is(equal_to(json!([
+                {"method": "nvmf_create_transport"},
+                {"method": "nvmf_subsystem_create"}
+            ])))
+        );
+    }
+}
@@ -1,17 +1,38 @@
-extern crate spdk-sys as spdk;
+#[macro_use]
+extern crate failure;
+#[macro_use]
+extern crate lazy_static;
+extern crate spdk_sys as spdk;
 
 pub mod bdev;
 pub mod bdev_module;
+pub mod conf;
 pub mod context;
+pub mod cpuset;
 pub mod env;
 pub mod event;
 pub mod executor;
 pub mod io_channel;
+pub mod json;
+pub mod log;
+pub mod mem;
+pub mod memzone;
+pub mod nvme;
+pub mod nvmf;
+pub mod pci;
+pub mod reactor;
+pub mod remote_bdev;
+pub mod ring;
 pub mod run;
 pub mod thread;
+pub mod time;
+pub mod util;
 
 pub use bdev::{SpdkBdev, SpdkBdevDesc};
 pub use bdev_module::SpdkBdevIO;
 pub use context::{AppContext, SpdkBdevIoCompletionCb};
+pub use cpuset::CpuSet;
 pub use env::Buf;
 pub use event::{app_stop, SpdkAppOpts};
+pub use executor::start_async;
+pub use remote_bdev::RemoteBdev;
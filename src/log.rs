@@ -0,0 +1,87 @@
+use spdk;
+
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+
+/// spdk_log_level
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Disabled,
+    Error,
+    Warn,
+    Notice,
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    pub fn to_raw(self) -> spdk::spdk_log_level {
+        match self {
+            LogLevel::Disabled => spdk::spdk_log_level_SPDK_LOG_DISABLED,
+            LogLevel::Error => spdk::spdk_log_level_SPDK_LOG_ERROR,
+            LogLevel::Warn => spdk::spdk_log_level_SPDK_LOG_WARN,
+            LogLevel::Notice => spdk::spdk_log_level_SPDK_LOG_NOTICE,
+            LogLevel::Info => spdk::spdk_log_level_SPDK_LOG_INFO,
+            LogLevel::Debug => spdk::spdk_log_level_SPDK_LOG_DEBUG,
+        }
+    }
+
+    pub fn from_raw(raw: spdk::spdk_log_level) -> LogLevel {
+        match raw {
+            spdk::spdk_log_level_SPDK_LOG_ERROR => LogLevel::Error,
+            spdk::spdk_log_level_SPDK_LOG_WARN => LogLevel::Warn,
+            spdk::spdk_log_level_SPDK_LOG_NOTICE => LogLevel::Notice,
+            spdk::spdk_log_level_SPDK_LOG_INFO => LogLevel::Info,
+            spdk::spdk_log_level_SPDK_LOG_DEBUG => LogLevel::Debug,
+            _ => LogLevel::Disabled,
+        }
+    }
+}
+
+/// spdk_log_set_level()
+///
+/// Messages below this level are not logged at all.
+pub fn set_level(level: LogLevel) {
+    unsafe { spdk::spdk_log_set_level(level.to_raw()) }
+}
+
+/// spdk_log_get_level()
+pub fn level() -> LogLevel {
+    LogLevel::from_raw(unsafe { spdk::spdk_log_get_level() })
+}
+
+/// spdk_log_set_print_level()
+///
+/// Messages at or above this level are also printed to stderr.
+pub fn set_print_level(level: LogLevel) {
+    unsafe { spdk::spdk_log_set_print_level(level.to_raw()) }
+}
+
+/// spdk_log_get_print_level()
+pub fn print_level() -> LogLevel {
+    LogLevel::from_raw(unsafe { spdk::spdk_log_get_print_level() })
+}
+
+/// spdk_log()
+///
+/// Logs `message` like the C code does, as if it came from `func` at
+/// `file:line`. It is subject to `set_level` and `set_print_level`.
+pub fn log(level: LogLevel, file: &str, line: u32, func: &str, message: &str) {
+    let file = to_cstring(file);
+    let func = to_cstring(func);
+    let message = to_cstring(message);
+    unsafe {
+        spdk::spdk_log(
+            level.to_raw(),
+            file.as_ptr(),
+            line as c_int,
+            func.as_ptr(),
+            b"%s\n\0".as_ptr() as *const c_char,
+            message.as_ptr(),
+        )
+    }
+}
+
+fn to_cstring(s: &str) -> CString {
+    CString::new(s.replace('\0', "")).expect("Couldn't create a string")
+}
@@ -0,0 +1,263 @@
+//! Registration of externally allocated memory and virtual address
+//! translation maps.
+//!
+//! Memory that does not come from the SPDK allocators (`env::dma_zmalloc`
+//! and friends) has to be registered before it can be handed to a driver.
+//! Every registration and unregistration is reported to the `MemMap`s, which
+//! is how transports keep their own translations (RDMA memory regions,
+//! physical addresses...) up to date.
+
+use spdk;
+
+use std::ffi::c_void;
+use std::ops::Deref;
+use std::os::raw::c_int;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use failure::Error;
+
+/// SPDK_VTOPHYS_ERROR
+const VTOPHYS_ERROR: u64 = std::u64::MAX;
+
+#[derive(Debug, Fail)]
+pub enum MemError {
+    #[fail(display = "Could not register {:#x} ({} bytes): {}", _0, _1, _2)]
+    RegisterError(usize, usize, i32),
+
+    #[fail(display = "Could not allocate a memory map")]
+    MapAllocError(),
+
+    #[fail(
+        display = "Could not change the translation of {:#x} ({} bytes): {}",
+        _0, _1, _2
+    )]
+    TranslationError(u64, u64, i32),
+}
+
+/// A registered memory region, unregistered when dropped. See `register`.
+pub struct Registration {
+    vaddr: *mut c_void,
+    len: usize,
+}
+
+unsafe impl Send for Registration {}
+
+impl Registration {
+    pub fn vaddr(&self) -> *mut c_void {
+        self.vaddr
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Drop for Registration {
+    /// spdk_mem_unregister()
+    fn drop(&mut self) {
+        unsafe { spdk::spdk_mem_unregister(self.vaddr, self.len) };
+    }
+}
+
+/// spdk_mem_register()
+///
+/// Both `vaddr` and `len` must be 2MB aligned.
+///
+/// # Safety
+///
+/// The region must be hugepage-backed and stay mapped until the returned
+/// `Registration` is dropped.
+pub unsafe fn register(vaddr: *mut c_void, len: usize) -> Result<Registration, Error> {
+    let rc = spdk::spdk_mem_register(vaddr, len);
+    if rc != 0 {
+        return Err(MemError::RegisterError(vaddr as usize, len, rc))?;
+    }
+    Ok(Registration { vaddr, len })
+}
+
+/// spdk_vtophys()
+///
+/// Returns the physical address of `buf`, or `None` if it is not backed by
+/// registered memory.
+pub fn vtophys(buf: *const c_void) -> Option<u64> {
+    from_vtophys(unsafe { spdk::spdk_vtophys(buf as *mut c_void, ptr::null_mut()) })
+}
+
+fn from_vtophys(paddr: u64) -> Option<u64> {
+    match paddr {
+        VTOPHYS_ERROR => None,
+        paddr => Some(paddr),
+    }
+}
+
+/// spdk_mem_map_notify_action
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NotifyAction {
+    Register,
+    Unregister,
+}
+
+impl NotifyAction {
+    pub fn from_raw(raw: spdk::spdk_mem_map_notify_action) -> NotifyAction {
+        match raw {
+            spdk::spdk_mem_map_notify_action_SPDK_MEM_MAP_NOTIFY_REGISTER => NotifyAction::Register,
+            _ => NotifyAction::Unregister,
+        }
+    }
+}
+
+type NotifyFn = dyn FnMut(&MemMapRef, NotifyAction, *mut c_void, usize) -> Result<(), Error> + Send;
+
+/// Borrowed `spdk_mem_map`, as handed to the notify callback of a `MemMap`.
+pub struct MemMapRef {
+    raw: *mut spdk::spdk_mem_map,
+}
+
+unsafe impl Send for MemMapRef {}
+unsafe impl Sync for MemMapRef {}
+
+impl MemMapRef {
+    pub fn to_raw(&self) -> *mut spdk::spdk_mem_map {
+        self.raw
+    }
+
+    /// spdk_mem_map_set_translation()
+    ///
+    /// `vaddr` and `size` must be 2MB aligned.
+    pub fn set_translation(&self, vaddr: u64, size: u64, translation: u64) -> Result<(), Error> {
+        let rc = unsafe { spdk::spdk_mem_map_set_translation(self.raw, vaddr, size, translation) };
+        if rc != 0 {
+            return Err(MemError::TranslationError(vaddr, size, rc))?;
+        }
+        Ok(())
+    }
+
+    /// spdk_mem_map_clear_translation()
+    pub fn clear_translation(&self, vaddr: u64, size: u64) -> Result<(), Error> {
+        let rc = unsafe { spdk::spdk_mem_map_clear_translation(self.raw, vaddr, size) };
+        if rc != 0 {
+            return Err(MemError::TranslationError(vaddr, size, rc))?;
+        }
+        Ok(())
+    }
+
+    /// spdk_mem_map_translate()
+    ///
+    /// Returns the translation of `vaddr`, or the default translation of the
+    /// map if there is none, along with how many of the `size` bytes starting
+    /// at `vaddr` share the same translation.
+    pub fn translate(&self, vaddr: u64, size: u64) -> (u64, u64) {
+        let mut size = size;
+        let translation = unsafe { spdk::spdk_mem_map_translate(self.raw, vaddr, &mut size) };
+        (translation, size)
+    }
+}
+
+/// Owned `spdk_mem_map`, freed when dropped.
+pub struct MemMap {
+    map: MemMapRef,
+    notify: *mut Box<NotifyFn>,
+}
+
+unsafe impl Send for MemMap {}
+unsafe impl Sync for MemMap {}
+
+impl MemMap {
+    /// spdk_mem_map_alloc()
+    ///
+    /// `notify` is called for every region already registered before this
+    /// returns, then for every later registration and unregistration, from
+    /// the thread doing it. Failing a registration, or panicking, makes
+    /// `register` fail.
+    pub fn new<F>(default_translation: u64, notify: F) -> Result<MemMap, Error>
+    where
+        F: FnMut(&MemMapRef, NotifyAction, *mut c_void, usize) -> Result<(), Error>
+            + Send
+            + 'static,
+    {
+        extern "C" fn notify_wrapper(
+            ctx: *mut c_void,
+            map: *mut spdk::spdk_mem_map,
+            action: spdk::spdk_mem_map_notify_action,
+            vaddr: *mut c_void,
+            size: usize,
+        ) -> c_int {
+            let notify = unsafe { &mut *(ctx as *mut Box<NotifyFn>) };
+            let map = MemMapRef { raw: map };
+            // Unwinding into SPDK is undefined behavior.
+            let res = panic::catch_unwind(AssertUnwindSafe(|| {
+                notify(&map, NotifyAction::from_raw(action), vaddr, size)
+            }));
+            match res {
+                Ok(Ok(())) => 0,
+                _ => -1,
+            }
+        }
+
+        let ops = spdk::spdk_mem_map_ops {
+            notify_cb: Some(notify_wrapper),
+            are_contiguous: None,
+        };
+        let boxed: Box<NotifyFn> = Box::new(notify);
+        let notify = Box::into_raw(Box::new(boxed));
+        let raw =
+            unsafe { spdk::spdk_mem_map_alloc(default_translation, &ops, notify as *mut c_void) };
+        if raw.is_null() {
+            drop(unsafe { Box::from_raw(notify) });
+            return Err(MemError::MapAllocError())?;
+        }
+        Ok(MemMap {
+            map: MemMapRef { raw },
+            notify,
+        })
+    }
+}
+
+impl Deref for MemMap {
+    type Target = MemMapRef;
+
+    fn deref(&self) -> &MemMapRef {
+        &self.map
+    }
+}
+
+impl Drop for MemMap {
+    /// spdk_mem_map_free()
+    fn drop(&mut self) {
+        let mut raw = self.map.raw;
+        unsafe {
+            spdk::spdk_mem_map_free(&mut raw);
+            drop(Box::from_raw(self.notify));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use hamcrest2::prelude::*;
+
+    #[test]
+    fn notify_actions_are_mapped() {
+        assert_that!(
+            NotifyAction::from_raw(spdk::spdk_mem_map_notify_action_SPDK_MEM_MAP_NOTIFY_REGISTER),
+            is(equal_to(NotifyAction::Register))
+        );
+        assert_that!(
+            NotifyAction::from_raw(spdk::spdk_mem_map_notify_action_SPDK_MEM_MAP_NOTIFY_UNREGISTER),
+            is(equal_to(NotifyAction::Unregister))
+        );
+    }
+
+    #[test]
+    fn vtophys_errors_are_mapped() {
+        assert_that!(from_vtophys(0x1000), is(equal_to(Some(0x1000))));
+        assert_that!(from_vtophys(VTOPHYS_ERROR), is(equal_to(None)));
+    }
+}
@@ -0,0 +1,235 @@
+//! Named shared memory zones, visible to every process of the same `shm_id`.
+//!
+//! A primary process reserves a zone holding a `T`, secondary processes look
+//! it up by name and read or update the value in place. `T` is copied in and
+//! out of the zone, so it should be `#[repr(C)]` and must not hold pointers:
+//! the zone is not necessarily mapped at the same address in every process.
+
+use crate::util;
+use spdk;
+
+use std::ffi::{c_void, CString};
+use std::mem;
+use std::ptr;
+
+use failure::Error;
+
+/// RTE_MEMZONE_NAMESIZE, including the terminating NUL byte.
+const NAME_SIZE: usize = 32;
+
+/// Marks zones reserved through `Memzone`.
+const MAGIC: u64 = 0x5350_444b_4d5a_4f4e;
+
+#[derive(Debug, Fail)]
+pub enum MemzoneError {
+    #[fail(display = "Invalid memzone name: {:?}", _0)]
+    InvalidName(String),
+
+    #[fail(display = "Could not reserve memzone {}", _0)]
+    ReserveError(String),
+
+    #[fail(display = "Memzone {} not found", _0)]
+    NotFound(String),
+
+    #[fail(display = "Memzone {} was not reserved by this library", _0)]
+    BadHeader(String),
+
+    #[fail(display = "Memzone {} holds {} bytes, expected {}", _0, _1, _2)]
+    SizeMismatch(String, u64, u64),
+
+    #[fail(display = "Could not free memzone {}: {}", _0, _1)]
+    FreeError(String, i32),
+}
+
+/// Written in front of the value so that `lookup` can tell whether the zone
+/// holds a `T`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Header {
+    magic: u64,
+    size: u64,
+}
+
+#[repr(C)]
+struct Zone<T: Copy> {
+    header: Header,
+    value: T,
+}
+
+/// A memzone holding a `T`. The zone is freed when the handle that reserved
+/// it is dropped, handles returned by `lookup` leave it alone.
+pub struct Memzone<T: Copy> {
+    name: String,
+    raw: *mut Zone<T>,
+    owned: bool,
+}
+
+unsafe impl<T: Copy + Send> Send for Memzone<T> {}
+
+impl<T: Copy> Memzone<T> {
+    /// spdk_memzone_reserve()
+    ///
+    /// Reserves the zone `name` on any socket and stores `value` in it.
+    pub fn reserve(name: &str, value: T) -> Result<Memzone<T>, Error> {
+        let c_name = check_name(name)?;
+        let raw = unsafe {
+            spdk::spdk_memzone_reserve(
+                c_name.as_ptr(),
+                mem::size_of::<Zone<T>>(),
+                spdk::SPDK_ENV_SOCKET_ID_ANY,
+                0,
+            )
+        };
+        Memzone::init(name, raw, value)
+    }
+
+    /// spdk_memzone_reserve_aligned()
+    ///
+    /// Same as `reserve`, with the zone aligned on `align` bytes, a power of two.
+    pub fn reserve_aligned(name: &str, value: T, align: u32) -> Result<Memzone<T>, Error> {
+        let c_name = check_name(name)?;
+        let raw = unsafe {
+            spdk::spdk_memzone_reserve_aligned(
+                c_name.as_ptr(),
+                mem::size_of::<Zone<T>>(),
+                spdk::SPDK_ENV_SOCKET_ID_ANY,
+                0,
+                align,
+            )
+        };
+        Memzone::init(name, raw, value)
+    }
+
+    fn init(name: &str, raw: *mut c_void, value: T) -> Result<Memzone<T>, Error> {
+        if raw.is_null() {
+            return Err(MemzoneError::ReserveError(name.to_string()))?;
+        }
+        let raw = raw as *mut Zone<T>;
+        unsafe {
+            ptr::write_volatile(
+                raw,
+                Zone {
+                    header: Header {
+                        magic: MAGIC,
+                        size: mem::size_of::<T>() as u64,
+                    },
+                    value,
+                },
+            )
+        };
+        Ok(Memzone {
+            name: name.to_string(),
+            raw,
+            owned: true,
+        })
+    }
+
+    /// spdk_memzone_lookup()
+    ///
+    /// Finds a zone reserved by `reserve` with the same `T`, in this process
+    /// or another one.
+    pub fn lookup(name: &str) -> Result<Memzone<T>, Error> {
+        let c_name = check_name(name)?;
+        let raw = unsafe { spdk::spdk_memzone_lookup(c_name.as_ptr()) } as *mut Zone<T>;
+        if raw.is_null() {
+            return Err(MemzoneError::NotFound(name.to_string()))?;
+        }
+        let header = unsafe { ptr::read_volatile(&(*raw).header) };
+        if header.magic != MAGIC {
+            return Err(MemzoneError::BadHeader(name.to_string()))?;
+        }
+        if header.size != mem::size_of::<T>() as u64 {
+            return Err(MemzoneError::SizeMismatch(
+                name.to_string(),
+                header.size,
+                mem::size_of::<T>() as u64,
+            ))?;
+        }
+        Ok(Memzone {
+            name: name.to_string(),
+            raw,
+            owned: false,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// True if this handle reserved the zone and frees it when dropped.
+    pub fn is_owner(&self) -> bool {
+        self.owned
+    }
+
+    /// Copies the value out of the zone.
+    pub fn get(&self) -> T {
+        unsafe { ptr::read_volatile(&(*self.raw).value) }
+    }
+
+    /// Copies `value` into the zone.
+    pub fn set(&self, value: T) {
+        unsafe { ptr::write_volatile(&mut (*self.raw).value, value) }
+    }
+
+    /// Address of the value in this process.
+    pub fn as_ptr(&self) -> *mut T {
+        unsafe { &mut (*self.raw).value }
+    }
+
+    /// spdk_memzone_free()
+    ///
+    /// Frees the zone right away, whether this handle reserved it or not.
+    pub fn free(mut self) -> Result<(), Error> {
+        self.owned = false;
+        free(&self.name)
+    }
+}
+
+impl<T: Copy> Drop for Memzone<T> {
+    fn drop(&mut self) {
+        if self.owned {
+            let _ = free(&self.name);
+        }
+    }
+}
+
+fn free(name: &str) -> Result<(), Error> {
+    let c_name = check_name(name)?;
+    let rc = unsafe { spdk::spdk_memzone_free(c_name.as_ptr()) };
+    if rc != 0 {
+        return Err(MemzoneError::FreeError(name.to_string(), rc))?;
+    }
+    Ok(())
+}
+
+/// spdk_memzone_dump()
+///
+/// Describes every memzone of the process group.
+pub fn dump() -> Result<String, Error> {
+    util::with_memstream(|fp| unsafe { spdk::spdk_memzone_dump(fp) })
+}
+
+/// Memzone names are non-empty, without NUL bytes, and short enough to fit
+/// in the name of a DPDK memzone.
+fn check_name(name: &str) -> Result<CString, Error> {
+    if name.is_empty() || name.len() >= NAME_SIZE {
+        return Err(MemzoneError::InvalidName(name.to_string()))?;
+    }
+    CString::new(name).map_err(|_| MemzoneError::InvalidName(name.to_string()).into())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use hamcrest2::prelude::*;
+
+    #[test]
+    fn names_are_checked() {
+        assert_that!(check_name("stats"), is(ok()));
+        assert_that!(check_name(&"x".repeat(NAME_SIZE - 1)), is(ok()));
+        assert_that!(check_name(""), is(err()));
+        assert_that!(check_name(&"x".repeat(NAME_SIZE)), is(err()));
+        assert_that!(check_name("st\0ats"), is(err()));
+    }
+}
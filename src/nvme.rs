@@ -0,0 +1,691 @@
+//! NVMe driver: controller discovery and attachment.
+//!
+//! Controllers are driven directly from the calling thread, without bdevs or
+//! reactors, so this only needs the environment to be initialized, see
+//! `env::Env::init`.
+
+use spdk;
+
+use std::any::Any;
+use std::ffi::{c_void, CStr, CString};
+use std::fmt;
+use std::mem;
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use failure::Error;
+
+#[derive(Debug, Fail)]
+pub enum NvmeError {
+    #[fail(display = "Invalid transport ID: {}", _0)]
+    InvalidTransportId(String),
+
+    #[fail(display = "Invalid transport type: {}", _0)]
+    InvalidTransportType(String),
+
+    #[fail(display = "Invalid address family: {}", _0)]
+    InvalidAddressFamily(String),
+
+    #[fail(display = "Invalid {} for a transport ID: {}", _0, _1)]
+    InvalidField(&'static str, String),
+
+    #[fail(display = "Could not probe NVMe controllers: {}", _0)]
+    ProbeError(i32),
+
+    #[fail(display = "Could not connect to {}", _0)]
+    ConnectError(String),
+
+    #[fail(display = "Could not process admin completions: {}", _0)]
+    AdminError(i32),
+}
+
+/// spdk_nvme_transport_type
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransportType {
+    Pcie,
+    Rdma,
+    Fc,
+    Tcp,
+}
+
+impl TransportType {
+    pub fn to_raw(self) -> spdk::spdk_nvme_transport_type {
+        match self {
+            TransportType::Pcie => spdk::spdk_nvme_transport_type_SPDK_NVME_TRANSPORT_PCIE,
+            TransportType::Rdma => spdk::spdk_nvme_transport_type_SPDK_NVME_TRANSPORT_RDMA,
+            TransportType::Fc => spdk::spdk_nvme_transport_type_SPDK_NVME_TRANSPORT_FC,
+            TransportType::Tcp => spdk::spdk_nvme_transport_type_SPDK_NVME_TRANSPORT_TCP,
+        }
+    }
+
+    pub fn from_raw(raw: spdk::spdk_nvme_transport_type) -> Option<TransportType> {
+        match raw {
+            spdk::spdk_nvme_transport_type_SPDK_NVME_TRANSPORT_PCIE => Some(TransportType::Pcie),
+            spdk::spdk_nvme_transport_type_SPDK_NVME_TRANSPORT_RDMA => Some(TransportType::Rdma),
+            spdk::spdk_nvme_transport_type_SPDK_NVME_TRANSPORT_FC => Some(TransportType::Fc),
+            spdk::spdk_nvme_transport_type_SPDK_NVME_TRANSPORT_TCP => Some(TransportType::Tcp),
+            _ => None,
+        }
+    }
+
+    /// spdk_nvme_transport_available()
+    ///
+    /// False if SPDK was built without support for this transport.
+    pub fn is_available(self) -> bool {
+        unsafe { spdk::spdk_nvme_transport_available(self.to_raw()) }
+    }
+}
+
+impl FromStr for TransportType {
+    type Err = Error;
+
+    /// spdk_nvme_transport_id_parse_trtype()
+    ///
+    /// Case insensitive.
+    fn from_str(trtype: &str) -> Result<TransportType, Error> {
+        let invalid = || NvmeError::InvalidTransportType(trtype.to_string());
+        let c_trtype = CString::new(trtype).map_err(|_| invalid())?;
+        let mut raw: spdk::spdk_nvme_transport_type = 0;
+        if unsafe { spdk::spdk_nvme_transport_id_parse_trtype(&mut raw, c_trtype.as_ptr()) } != 0 {
+            return Err(invalid())?;
+        }
+        Ok(TransportType::from_raw(raw).ok_or_else(invalid)?)
+    }
+}
+
+impl fmt::Display for TransportType {
+    /// spdk_nvme_transport_id_trtype_str()
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = unsafe { CStr::from_ptr(spdk::spdk_nvme_transport_id_trtype_str(self.to_raw())) };
+        write!(f, "{}", s.to_string_lossy())
+    }
+}
+
+/// spdk_nvmf_adrfam
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressFamily {
+    Ipv4,
+    Ipv6,
+    Ib,
+    Fc,
+    IntraHost,
+}
+
+impl AddressFamily {
+    pub fn to_raw(self) -> spdk::spdk_nvmf_adrfam {
+        match self {
+            AddressFamily::Ipv4 => spdk::spdk_nvmf_adrfam_SPDK_NVMF_ADRFAM_IPV4,
+            AddressFamily::Ipv6 => spdk::spdk_nvmf_adrfam_SPDK_NVMF_ADRFAM_IPV6,
+            AddressFamily::Ib => spdk::spdk_nvmf_adrfam_SPDK_NVMF_ADRFAM_IB,
+            AddressFamily::Fc => spdk::spdk_nvmf_adrfam_SPDK_NVMF_ADRFAM_FC,
+            AddressFamily::IntraHost => spdk::spdk_nvmf_adrfam_SPDK_NVMF_ADRFAM_INTRA_HOST,
+        }
+    }
+
+    pub fn from_raw(raw: spdk::spdk_nvmf_adrfam) -> Option<AddressFamily> {
+        match raw {
+            spdk::spdk_nvmf_adrfam_SPDK_NVMF_ADRFAM_IPV4 => Some(AddressFamily::Ipv4),
+            spdk::spdk_nvmf_adrfam_SPDK_NVMF_ADRFAM_IPV6 => Some(AddressFamily::Ipv6),
+            spdk::spdk_nvmf_adrfam_SPDK_NVMF_ADRFAM_IB => Some(AddressFamily::Ib),
+            spdk::spdk_nvmf_adrfam_SPDK_NVMF_ADRFAM_FC => Some(AddressFamily::Fc),
+            spdk::spdk_nvmf_adrfam_SPDK_NVMF_ADRFAM_INTRA_HOST => Some(AddressFamily::IntraHost),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for AddressFamily {
+    type Err = Error;
+
+    /// spdk_nvme_transport_id_parse_adrfam()
+    ///
+    /// Case insensitive.
+    fn from_str(adrfam: &str) -> Result<AddressFamily, Error> {
+        let invalid = || NvmeError::InvalidAddressFamily(adrfam.to_string());
+        let c_adrfam = CString::new(adrfam).map_err(|_| invalid())?;
+        let mut raw: spdk::spdk_nvmf_adrfam = 0;
+        if unsafe { spdk::spdk_nvme_transport_id_parse_adrfam(&mut raw, c_adrfam.as_ptr()) } != 0 {
+            return Err(invalid())?;
+        }
+        Ok(AddressFamily::from_raw(raw).ok_or_else(invalid)?)
+    }
+}
+
+impl fmt::Display for AddressFamily {
+    /// spdk_nvme_transport_id_adrfam_str()
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = unsafe { CStr::from_ptr(spdk::spdk_nvme_transport_id_adrfam_str(self.to_raw())) };
+        write!(f, "{}", s.to_string_lossy())
+    }
+}
+
+/// spdk_nvme_transport_id
+///
+/// Parsed from and formatted to the `key:value` pairs used in config files
+/// and RPCs, e.g. `trtype:TCP adrfam:IPv4 traddr:10.0.0.1 trsvcid:4420`.
+#[derive(Clone, Copy)]
+pub struct TransportId {
+    raw: spdk::spdk_nvme_transport_id,
+}
+
+impl TransportId {
+    /// A transport ID with only the transport type set.
+    pub fn new(trtype: TransportType) -> TransportId {
+        let mut raw: spdk::spdk_nvme_transport_id = unsafe { mem::zeroed() };
+        raw.trtype = trtype.to_raw();
+        TransportId { raw }
+    }
+
+    /// Every local PCIe controller.
+    pub fn pcie() -> TransportId {
+        TransportId::new(TransportType::Pcie)
+    }
+
+    pub fn from_raw(raw: spdk::spdk_nvme_transport_id) -> TransportId {
+        TransportId { raw }
+    }
+
+    pub fn to_raw(&self) -> *const spdk::spdk_nvme_transport_id {
+        &self.raw
+    }
+
+    /// `None` if the raw transport type is not a known one.
+    pub fn trtype(&self) -> Option<TransportType> {
+        TransportType::from_raw(self.raw.trtype)
+    }
+
+    /// `None` if not set, as for PCIe.
+    pub fn adrfam(&self) -> Option<AddressFamily> {
+        AddressFamily::from_raw(self.raw.adrfam)
+    }
+
+    /// PCI address, IP address or FC WWN, depending on the transport.
+    pub fn traddr(&self) -> String {
+        field_str(&self.raw.traddr)
+    }
+
+    pub fn trsvcid(&self) -> String {
+        field_str(&self.raw.trsvcid)
+    }
+
+    pub fn subnqn(&self) -> String {
+        field_str(&self.raw.subnqn)
+    }
+
+    pub fn set_adrfam(&mut self, adrfam: AddressFamily) -> &mut Self {
+        self.raw.adrfam = adrfam.to_raw();
+        self
+    }
+
+    /// PCI address, IP address or FC WWN, depending on the transport.
+    pub fn set_traddr(&mut self, traddr: &str) -> Result<&mut Self, Error> {
+        set_field(&mut self.raw.traddr, "traddr", traddr)?;
+        Ok(self)
+    }
+
+    /// Port or service name.
+    pub fn set_trsvcid(&mut self, trsvcid: &str) -> Result<&mut Self, Error> {
+        set_field(&mut self.raw.trsvcid, "trsvcid", trsvcid)?;
+        Ok(self)
+    }
+
+    pub fn set_subnqn(&mut self, subnqn: &str) -> Result<&mut Self, Error> {
+        set_field(&mut self.raw.subnqn, "subnqn", subnqn)?;
+        Ok(self)
+    }
+}
+
+impl FromStr for TransportId {
+    type Err = Error;
+
+    /// spdk_nvme_transport_id_parse()
+    fn from_str(trid: &str) -> Result<TransportId, Error> {
+        let invalid = || NvmeError::InvalidTransportId(trid.to_string());
+        let c_trid = CString::new(trid).map_err(|_| invalid())?;
+        let mut raw: spdk::spdk_nvme_transport_id = unsafe { mem::zeroed() };
+        if unsafe { spdk::spdk_nvme_transport_id_parse(&mut raw, c_trid.as_ptr()) } != 0 {
+            return Err(invalid())?;
+        }
+        Ok(TransportId { raw })
+    }
+}
+
+impl fmt::Display for TransportId {
+    /// Formats the fields that are set, in the format `FromStr` accepts.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.trtype() {
+            Some(trtype) => write!(f, "trtype:{}", trtype)?,
+            None => write!(f, "trtype:{}", self.raw.trtype)?,
+        }
+        if let Some(adrfam) = self.adrfam() {
+            write!(f, " adrfam:{}", adrfam)?;
+        }
+        for (key, value) in &[
+            ("traddr", self.traddr()),
+            ("trsvcid", self.trsvcid()),
+            ("subnqn", self.subnqn()),
+        ] {
+            if !value.is_empty() {
+                write!(f, " {}:{}", key, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for TransportId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TransportId({})", self)
+    }
+}
+
+impl PartialEq for TransportId {
+    /// spdk_nvme_transport_id_compare()
+    fn eq(&self, other: &TransportId) -> bool {
+        unsafe { spdk::spdk_nvme_transport_id_compare(&self.raw, &other.raw) == 0 }
+    }
+}
+
+impl Eq for TransportId {}
+
+/// spdk_nvme_ctrlr_opts
+///
+/// Options a controller is attached with. The probe callback of `probe_with`
+/// gets the options SPDK is about to use and can change them.
+#[repr(transparent)]
+pub struct ControllerOpts {
+    raw: spdk::spdk_nvme_ctrlr_opts,
+}
+
+impl ControllerOpts {
+    /// spdk_nvme_ctrlr_get_default_ctrlr_opts()
+    pub fn new() -> ControllerOpts {
+        let mut raw: spdk::spdk_nvme_ctrlr_opts = unsafe { mem::zeroed() };
+        unsafe {
+            spdk::spdk_nvme_ctrlr_get_default_ctrlr_opts(
+                &mut raw,
+                mem::size_of::<spdk::spdk_nvme_ctrlr_opts>(),
+            )
+        };
+        ControllerOpts { raw }
+    }
+
+    fn from_raw_mut<'a>(raw: *mut spdk::spdk_nvme_ctrlr_opts) -> &'a mut ControllerOpts {
+        unsafe { &mut *(raw as *mut ControllerOpts) }
+    }
+
+    pub fn to_raw(&self) -> *const spdk::spdk_nvme_ctrlr_opts {
+        &self.raw
+    }
+
+    pub fn num_io_queues(&mut self, num_io_queues: u32) -> &mut Self {
+        self.raw.num_io_queues = num_io_queues;
+        self
+    }
+
+    /// Number of entries of each I/O queue.
+    pub fn io_queue_size(&mut self, io_queue_size: u32) -> &mut Self {
+        self.raw.io_queue_size = io_queue_size;
+        self
+    }
+
+    /// Number of requests allocated for each I/O queue.
+    pub fn io_queue_requests(&mut self, io_queue_requests: u32) -> &mut Self {
+        self.raw.io_queue_requests = io_queue_requests;
+        self
+    }
+
+    /// Put the submission queues in the controller memory buffer.
+    pub fn use_cmb_sqs(&mut self, use_cmb_sqs: bool) -> &mut Self {
+        self.raw.use_cmb_sqs = use_cmb_sqs;
+        self
+    }
+
+    /// Zero disables keep alive. Longer than `u32::MAX` milliseconds
+    /// saturates.
+    pub fn keep_alive_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.raw.keep_alive_timeout_ms = saturating_millis(timeout);
+        self
+    }
+
+    /// Longer than `u32::MAX` milliseconds saturates.
+    pub fn admin_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.raw.admin_timeout_ms = saturating_millis(timeout);
+        self
+    }
+
+    /// Host NQN of fabrics connections, truncated if too long.
+    pub fn hostnqn(&mut self, hostnqn: &str) -> &mut Self {
+        copy_str(&mut self.raw.hostnqn, hostnqn);
+        self
+    }
+
+    pub fn header_digest(&mut self, enabled: bool) -> &mut Self {
+        self.raw.header_digest = enabled;
+        self
+    }
+
+    pub fn data_digest(&mut self, enabled: bool) -> &mut Self {
+        self.raw.data_digest = enabled;
+        self
+    }
+}
+
+impl Default for ControllerOpts {
+    fn default() -> ControllerOpts {
+        ControllerOpts::new()
+    }
+}
+
+/// An attached NVMe controller, detached when dropped.
+pub struct Controller {
+    raw: *mut spdk::spdk_nvme_ctrlr,
+}
+
+// A controller can be moved to another thread, but its admin queue must not
+// be used from several threads at once.
+unsafe impl Send for Controller {}
+
+impl Controller {
+    pub fn to_raw(&self) -> *mut spdk::spdk_nvme_ctrlr {
+        self.raw
+    }
+
+    /// Serial number from the identify controller data.
+    pub fn serial_number(&self) -> String {
+        let sn = unsafe { (*spdk::spdk_nvme_ctrlr_get_data(self.raw)).sn };
+        fixed_str(&sn.iter().map(|c| *c as u8).collect::<Vec<_>>())
+    }
+
+    /// Model number from the identify controller data.
+    pub fn model_number(&self) -> String {
+        let mn = unsafe { (*spdk::spdk_nvme_ctrlr_get_data(self.raw)).mn };
+        fixed_str(&mn.iter().map(|c| *c as u8).collect::<Vec<_>>())
+    }
+
+    /// Firmware revision from the identify controller data.
+    pub fn firmware_revision(&self) -> String {
+        let fr = unsafe { (*spdk::spdk_nvme_ctrlr_get_data(self.raw)).fr };
+        fixed_str(&fr)
+    }
+
+    /// spdk_nvme_ctrlr_get_num_ns()
+    ///
+    /// Largest namespace ID, namespaces `1..=num_ns()` are not all active.
+    pub fn num_ns(&self) -> u32 {
+        unsafe { spdk::spdk_nvme_ctrlr_get_num_ns(self.raw) }
+    }
+
+    /// spdk_nvme_ctrlr_is_active_ns()
+    pub fn is_active_ns(&self, nsid: u32) -> bool {
+        unsafe { spdk::spdk_nvme_ctrlr_is_active_ns(self.raw, nsid) }
+    }
+
+    /// IDs of the active namespaces.
+    pub fn active_ns(&self) -> Vec<u32> {
+        (1..=self.num_ns())
+            .filter(|nsid| self.is_active_ns(*nsid))
+            .collect()
+    }
+
+    /// spdk_nvme_ctrlr_process_admin_completions()
+    ///
+    /// Returns the number of completions processed. Must be called
+    /// periodically to handle keep alive and asynchronous events.
+    pub fn process_admin_completions(&self) -> Result<u32, Error> {
+        let rc = unsafe { spdk::spdk_nvme_ctrlr_process_admin_completions(self.raw) };
+        if rc < 0 {
+            return Err(NvmeError::AdminError(rc))?;
+        }
+        Ok(rc as u32)
+    }
+}
+
+impl Drop for Controller {
+    /// spdk_nvme_detach()
+    fn drop(&mut self) {
+        unsafe { spdk::spdk_nvme_detach(self.raw) };
+    }
+}
+
+/// spdk_nvme_probe()
+///
+/// Attaches every controller found at `trid`, e.g. `TransportId::pcie()`
+/// for the local controllers, with the default options.
+pub fn probe(trid: &TransportId) -> Result<Vec<Controller>, Error> {
+    probe_with(trid, |_, _| true)
+}
+
+/// spdk_nvme_probe()
+///
+/// Calls `probe_cb` with the transport ID of every controller found at
+/// `trid` and the options it would be attached with. Only the controllers
+/// for which it returns true are attached. If it panics, no further
+/// controller is attached and the panic is resumed once probing is over.
+pub fn probe_with<F>(trid: &TransportId, probe_cb: F) -> Result<Vec<Controller>, Error>
+where
+    F: FnMut(&TransportId, &mut ControllerOpts) -> bool,
+{
+    struct Probe<F> {
+        probe_cb: F,
+        controllers: Vec<Controller>,
+        panic: Option<Box<dyn Any + Send>>,
+    }
+
+    extern "C" fn probe_wrapper<F>(
+        ctx: *mut c_void,
+        trid: *const spdk::spdk_nvme_transport_id,
+        opts: *mut spdk::spdk_nvme_ctrlr_opts,
+    ) -> bool
+    where
+        F: FnMut(&TransportId, &mut ControllerOpts) -> bool,
+    {
+        let probe = unsafe { &mut *(ctx as *mut Probe<F>) };
+        if probe.panic.is_some() {
+            return false;
+        }
+        let trid = TransportId::from_raw(unsafe { *trid });
+        let probe_cb = &mut probe.probe_cb;
+        // Unwinding into SPDK is undefined behavior.
+        match panic::catch_unwind(AssertUnwindSafe(|| {
+            probe_cb(&trid, ControllerOpts::from_raw_mut(opts))
+        })) {
+            Ok(attach) => attach,
+            Err(panic) => {
+                probe.panic = Some(panic);
+                false
+            }
+        }
+    }
+
+    extern "C" fn attach_wrapper<F>(
+        ctx: *mut c_void,
+        _trid: *const spdk::spdk_nvme_transport_id,
+        ctrlr: *mut spdk::spdk_nvme_ctrlr,
+        _opts: *const spdk::spdk_nvme_ctrlr_opts,
+    ) {
+        let probe = unsafe { &mut *(ctx as *mut Probe<F>) };
+        let controllers = &mut probe.controllers;
+        let attached = panic::catch_unwind(AssertUnwindSafe(|| {
+            controllers.push(Controller { raw: ctrlr })
+        }));
+        if let Err(panic) = attached {
+            probe.panic = Some(panic);
+        }
+    }
+
+    let mut probe = Probe {
+        probe_cb,
+        controllers: Vec::new(),
+        panic: None,
+    };
+    let rc = unsafe {
+        spdk::spdk_nvme_probe(
+            trid.to_raw(),
+            &mut probe as *mut Probe<F> as *mut c_void,
+            Some(probe_wrapper::<F>),
+            Some(attach_wrapper::<F>),
+            None,
+        )
+    };
+    if let Some(panic) = probe.panic.take() {
+        panic::resume_unwind(panic);
+    }
+    if rc != 0 {
+        return Err(NvmeError::ProbeError(rc))?;
+    }
+    Ok(probe.controllers)
+}
+
+/// spdk_nvme_connect()
+///
+/// Attaches the single controller at `trid`, which must include the
+/// address of the controller.
+pub fn connect(trid: &TransportId, opts: Option<&ControllerOpts>) -> Result<Controller, Error> {
+    let raw = unsafe {
+        match opts {
+            Some(opts) => spdk::spdk_nvme_connect(
+                trid.to_raw(),
+                opts.to_raw(),
+                mem::size_of::<spdk::spdk_nvme_ctrlr_opts>(),
+            ),
+            None => spdk::spdk_nvme_connect(trid.to_raw(), ptr::null(), 0),
+        }
+    };
+    if raw.is_null() {
+        return Err(NvmeError::ConnectError(trid.traddr()))?;
+    }
+    Ok(Controller { raw })
+}
+
+fn saturating_millis(duration: Duration) -> u32 {
+    duration.as_millis().min(u128::from(std::u32::MAX)) as u32
+}
+
+/// Copies `value` into a NUL terminated field of a transport ID, unless it
+/// does not fit.
+fn set_field(field: &mut [c_char], name: &'static str, value: &str) -> Result<(), Error> {
+    if value.len() >= field.len() || value.contains('\0') {
+        return Err(NvmeError::InvalidField(name, value.to_string()))?;
+    }
+    copy_str(field, value);
+    Ok(())
+}
+
+fn field_str(field: &[c_char]) -> String {
+    unsafe { CStr::from_ptr(field.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Copies `value` into a NUL terminated fixed size field, truncating it if
+/// needed.
+fn copy_str(field: &mut [c_char], value: &str) {
+    let len = value.len().min(field.len() - 1);
+    for (dst, src) in field.iter_mut().zip(value.bytes().take(len)) {
+        *dst = src as c_char;
+    }
+    for dst in field[len..].iter_mut() {
+        *dst = 0;
+    }
+}
+
+/// Identify data strings are padded with spaces, and sometimes NULs.
+fn fixed_str(field: &[u8]) -> String {
+    String::from_utf8_lossy(field)
+        .trim_end_matches(&[' ', '\0'][..])
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use hamcrest2::prelude::*;
+
+    #[test]
+    fn timeouts_saturate() {
+        assert_that!(
+            saturating_millis(Duration::from_secs(30)),
+            is(equal_to(30_000))
+        );
+        assert_that!(
+            saturating_millis(Duration::from_secs(u64::from(std::u32::MAX))),
+            is(equal_to(std::u32::MAX))
+        );
+    }
+
+    #[test]
+    fn transport_ids_are_parsed() {
+        let trid: TransportId = "trtype:TCP adrfam:IPv4 traddr:10.0.0.1 trsvcid:4420 \
+                                 subnqn:nqn.2016-06.io.spdk:cnode1"
+            .parse()
+            .unwrap();
+
+        assert_that!(trid.trtype(), is(equal_to(Some(TransportType::Tcp))));
+        assert_that!(trid.adrfam(), is(equal_to(Some(AddressFamily::Ipv4))));
+        assert_that!(trid.traddr().as_str(), is(equal_to("10.0.0.1")));
+        assert_that!(trid.trsvcid().as_str(), is(equal_to("4420")));
+        assert_that!(
+            trid.subnqn().as_str(),
+            is(equal_to("nqn.2016-06.io.spdk:cnode1"))
+        );
+    }
+
+    #[test]
+    fn transport_ids_round_trip() {
+        let mut trid = TransportId::new(TransportType::Rdma);
+        trid.set_adrfam(AddressFamily::Ipv6);
+        trid.set_traddr("fe80::1")
+            .unwrap()
+            .set_trsvcid("4420")
+            .unwrap();
+
+        let formatted = trid.to_string();
+        assert_that!(
+            formatted.as_str(),
+            is(equal_to(
+                "trtype:RDMA adrfam:IPv6 traddr:fe80::1 trsvcid:4420"
+            ))
+        );
+        assert_that!(
+            formatted.parse::<TransportId>().unwrap(),
+            is(equal_to(trid))
+        );
+
+        let pcie: TransportId = "trtype:pcie traddr:0000:04:00.0".parse().unwrap();
+        assert_that!(
+            pcie.to_string().as_str(),
+            is(equal_to("trtype:PCIe traddr:0000:04:00.0"))
+        );
+        assert_that!(pcie == trid, is(equal_to(false)));
+    }
+
+    #[test]
+    fn invalid_transport_ids_are_rejected() {
+        assert_that!("trtype:carrier-pigeon".parse::<TransportId>(), is(err()));
+        assert_that!("traddr".parse::<TransportId>(), is(err()));
+        assert_that!("pigeon".parse::<TransportType>(), is(err()));
+        assert_that!("IPv5".parse::<AddressFamily>(), is(err()));
+        assert_that!(TransportId::pcie().set_trsvcid(&"1".repeat(64)), is(err()));
+    }
+
+    #[test]
+    fn fixed_size_strings() {
+        assert_that!(
+            fixed_str(b"S3EVNX0K  \0\0").as_str(),
+            is(equal_to("S3EVNX0K"))
+        );
+
+        let mut field = [1 as c_char; 6];
+        copy_str(&mut field, "nqn.2014-08");
+        assert_that!(field[5], is(equal_to(0)));
+        assert_that!(
+            unsafe { CStr::from_ptr(field.as_ptr()) }.to_str().unwrap(),
+            is(equal_to("nqn.2"))
+        );
+    }
+}
@@ -0,0 +1,29 @@
+use crate::json;
+use spdk;
+
+use failure::Error;
+use serde_json::Value;
+
+/// `spdk_nvmf_tgt`, owned by the NVMe-oF target subsystem.
+#[derive(Clone)]
+pub struct NvmfTgt {
+    raw: *mut spdk::spdk_nvmf_tgt,
+}
+
+impl NvmfTgt {
+    pub fn from_raw(raw: *mut spdk::spdk_nvmf_tgt) -> NvmfTgt {
+        NvmfTgt { raw }
+    }
+
+    pub fn to_raw(&self) -> *mut spdk::spdk_nvmf_tgt {
+        self.raw
+    }
+
+    /// spdk_nvmf_tgt_write_config_json()
+    ///
+    /// RPC calls that recreate the target's transports and subsystems, as an
+    /// array.
+    pub fn config_json(&self) -> Result<Value, Error> {
+        json::write_json_array(|w| unsafe { spdk::spdk_nvmf_tgt_write_config_json(w, self.raw) })
+    }
+}
@@ -0,0 +1,436 @@
+//! PCI addresses, device enumeration and config space access.
+use spdk;
+
+use std::ffi::{c_void, CStr, CString};
+use std::fmt;
+use std::os::raw::{c_char, c_int};
+use std::str::FromStr;
+
+use failure::Error;
+
+#[derive(Debug, Fail)]
+pub enum PciError {
+    #[fail(display = "Invalid PCI address: {}", _0)]
+    ParseError(String),
+
+    #[fail(display = "Could not enumerate PCI devices: {}", _0)]
+    EnumerateError(i32),
+
+    #[fail(
+        display = "Could not access config space of {} at {:#x}: {}",
+        _0, _1, _2
+    )]
+    CfgError(PciAddr, u32, i32),
+
+    #[fail(display = "Could not read the serial number of {}: {}", _0, _1)]
+    SerialNumberError(PciAddr, i32),
+
+    #[fail(display = "Could not claim {}: {}", _0, _1)]
+    ClaimError(PciAddr, i32),
+}
+
+/// spdk_pci_addr
+///
+/// Parsed and formatted like spdk_pci_addr_parse() and spdk_pci_addr_fmt()
+/// do, and ordered like spdk_pci_addr_compare().
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PciAddr {
+    pub domain: u32,
+    pub bus: u8,
+    pub dev: u8,
+    pub func: u8,
+}
+
+impl PciAddr {
+    pub fn new(domain: u32, bus: u8, dev: u8, func: u8) -> PciAddr {
+        PciAddr {
+            domain,
+            bus,
+            dev,
+            func,
+        }
+    }
+
+    pub fn from_raw(raw: spdk::spdk_pci_addr) -> PciAddr {
+        PciAddr::new(raw.domain, raw.bus, raw.dev, raw.func)
+    }
+
+    pub fn to_raw(&self) -> spdk::spdk_pci_addr {
+        spdk::spdk_pci_addr {
+            domain: self.domain,
+            bus: self.bus,
+            dev: self.dev,
+            func: self.func,
+        }
+    }
+}
+
+impl FromStr for PciAddr {
+    type Err = Error;
+
+    /// spdk_pci_addr_parse()
+    ///
+    /// Accepts `domain:bus:dev.func`, `domain:bus:dev`, `bus:dev.func` and
+    /// `bus:dev`, all in hexadecimal. `.` can be used as the only separator
+    /// instead, in which case `a.b.c` is `bus.dev.func`.
+    fn from_str(bdf: &str) -> Result<PciAddr, Error> {
+        let bdf_cstring = match CString::new(bdf) {
+            Ok(bdf_cstring) => bdf_cstring,
+            Err(_) => return Err(PciError::ParseError(bdf.to_string()))?,
+        };
+        let mut raw = spdk::spdk_pci_addr::default();
+        let rc = unsafe { spdk::spdk_pci_addr_parse(&mut raw, bdf_cstring.as_ptr()) };
+        if rc != 0 {
+            return Err(PciError::ParseError(bdf.to_string()))?;
+        }
+        Ok(PciAddr::from_raw(raw))
+    }
+}
+
+impl fmt::Display for PciAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04x}:{:02x}:{:02x}.{:x}",
+            self.domain, self.bus, self.dev, self.func
+        )
+    }
+}
+
+/// PCI drivers of the environment, see `devices`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PciDriver {
+    Nvme,
+    Virtio,
+    Ioat,
+}
+
+impl PciDriver {
+    /// spdk_pci_nvme_get_driver() / spdk_pci_virtio_get_driver() /
+    /// spdk_pci_ioat_get_driver()
+    pub fn to_raw(self) -> *mut spdk::spdk_pci_driver {
+        unsafe {
+            match self {
+                PciDriver::Nvme => spdk::spdk_pci_nvme_get_driver(),
+                PciDriver::Virtio => spdk::spdk_pci_virtio_get_driver(),
+                PciDriver::Ioat => spdk::spdk_pci_ioat_get_driver(),
+            }
+        }
+    }
+}
+
+/// spdk_pci_id
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PciId {
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub subvendor_id: u16,
+    pub subdevice_id: u16,
+}
+
+/// A value of the config space, see `PciDevice::cfg_read`.
+pub trait CfgValue: Copy + Default {
+    /// spdk_pci_device_cfg_read8() / 16() / 32()
+    fn read(dev: &PciDevice, value: &mut Self, offset: u32) -> c_int;
+
+    /// spdk_pci_device_cfg_write8() / 16() / 32()
+    fn write(dev: &PciDevice, value: Self, offset: u32) -> c_int;
+}
+
+impl CfgValue for u8 {
+    fn read(dev: &PciDevice, value: &mut u8, offset: u32) -> c_int {
+        unsafe { spdk::spdk_pci_device_cfg_read8(dev.raw, value, offset) }
+    }
+
+    fn write(dev: &PciDevice, value: u8, offset: u32) -> c_int {
+        unsafe { spdk::spdk_pci_device_cfg_write8(dev.raw, value, offset) }
+    }
+}
+
+impl CfgValue for u16 {
+    fn read(dev: &PciDevice, value: &mut u16, offset: u32) -> c_int {
+        unsafe { spdk::spdk_pci_device_cfg_read16(dev.raw, value, offset) }
+    }
+
+    fn write(dev: &PciDevice, value: u16, offset: u32) -> c_int {
+        unsafe { spdk::spdk_pci_device_cfg_write16(dev.raw, value, offset) }
+    }
+}
+
+impl CfgValue for u32 {
+    fn read(dev: &PciDevice, value: &mut u32, offset: u32) -> c_int {
+        unsafe { spdk::spdk_pci_device_cfg_read32(dev.raw, value, offset) }
+    }
+
+    fn write(dev: &PciDevice, value: u32, offset: u32) -> c_int {
+        unsafe { spdk::spdk_pci_device_cfg_write32(dev.raw, value, offset) }
+    }
+}
+
+/// A PCI device attached by `devices`, detached when dropped.
+pub struct PciDevice {
+    raw: *mut spdk::spdk_pci_device,
+}
+
+unsafe impl Send for PciDevice {}
+
+impl PciDevice {
+    pub fn to_raw(&self) -> *mut spdk::spdk_pci_device {
+        self.raw
+    }
+
+    /// spdk_pci_device_get_addr()
+    pub fn addr(&self) -> PciAddr {
+        PciAddr::from_raw(unsafe { spdk::spdk_pci_device_get_addr(self.raw) })
+    }
+
+    /// spdk_pci_device_get_id()
+    pub fn id(&self) -> PciId {
+        let id = unsafe { spdk::spdk_pci_device_get_id(self.raw) };
+        PciId {
+            vendor_id: id.vendor_id,
+            device_id: id.device_id,
+            subvendor_id: id.subvendor_id,
+            subdevice_id: id.subdevice_id,
+        }
+    }
+
+    /// spdk_pci_device_get_vendor_id()
+    pub fn vendor_id(&self) -> u16 {
+        unsafe { spdk::spdk_pci_device_get_vendor_id(self.raw) }
+    }
+
+    /// spdk_pci_device_get_device_id()
+    pub fn device_id(&self) -> u16 {
+        unsafe { spdk::spdk_pci_device_get_device_id(self.raw) }
+    }
+
+    /// spdk_pci_device_get_socket_id()
+    ///
+    /// Returns `None` when the NUMA node of the device is unknown.
+    pub fn socket_id(&self) -> Option<u32> {
+        match unsafe { spdk::spdk_pci_device_get_socket_id(self.raw) } {
+            socket if socket < 0 => None,
+            socket => Some(socket as u32),
+        }
+    }
+
+    /// spdk_pci_device_get_serial_number()
+    ///
+    /// Reads the Device Serial Number capability.
+    pub fn serial_number(&self) -> Result<String, Error> {
+        let mut sn = [0 as c_char; 32];
+        let rc =
+            unsafe { spdk::spdk_pci_device_get_serial_number(self.raw, sn.as_mut_ptr(), sn.len()) };
+        if rc != 0 {
+            return Err(PciError::SerialNumberError(self.addr(), rc))?;
+        }
+        let sn = unsafe { CStr::from_ptr(sn.as_ptr()) };
+        Ok(sn.to_string_lossy().into_owned())
+    }
+
+    /// Reads a `u8`, `u16` or `u32` at `offset` of the config space.
+    pub fn cfg_read<T: CfgValue>(&self, offset: u32) -> Result<T, Error> {
+        let mut value = T::default();
+        let rc = T::read(self, &mut value, offset);
+        if rc != 0 {
+            return Err(PciError::CfgError(self.addr(), offset, rc))?;
+        }
+        Ok(value)
+    }
+
+    /// Writes a `u8`, `u16` or `u32` at `offset` of the config space.
+    pub fn cfg_write<T: CfgValue>(&self, offset: u32, value: T) -> Result<(), Error> {
+        let rc = T::write(self, value, offset);
+        if rc != 0 {
+            return Err(PciError::CfgError(self.addr(), offset, rc))?;
+        }
+        Ok(())
+    }
+
+    /// spdk_pci_device_cfg_read()
+    ///
+    /// Fills `buf` with the config space starting at `offset`.
+    pub fn cfg_read_bytes(&self, offset: u32, buf: &mut [u8]) -> Result<(), Error> {
+        let rc = unsafe {
+            spdk::spdk_pci_device_cfg_read(
+                self.raw,
+                buf.as_mut_ptr() as *mut c_void,
+                buf.len() as u32,
+                offset,
+            )
+        };
+        if rc != 0 {
+            return Err(PciError::CfgError(self.addr(), offset, rc))?;
+        }
+        Ok(())
+    }
+
+    /// spdk_pci_device_cfg_write()
+    pub fn cfg_write_bytes(&self, offset: u32, buf: &[u8]) -> Result<(), Error> {
+        let rc = unsafe {
+            spdk::spdk_pci_device_cfg_write(
+                self.raw,
+                buf.as_ptr() as *mut c_void,
+                buf.len() as u32,
+                offset,
+            )
+        };
+        if rc != 0 {
+            return Err(PciError::CfgError(self.addr(), offset, rc))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PciDevice {
+    /// spdk_pci_device_detach()
+    fn drop(&mut self) {
+        unsafe { spdk::spdk_pci_device_detach(self.raw) }
+    }
+}
+
+/// Iterates over the devices attached by `devices`.
+pub struct Devices {
+    devices: std::vec::IntoIter<PciDevice>,
+}
+
+impl Iterator for Devices {
+    type Item = PciDevice;
+
+    fn next(&mut self) -> Option<PciDevice> {
+        self.devices.next()
+    }
+}
+
+/// spdk_pci_enumerate()
+///
+/// Attaches every device handled by `driver` that is not attached yet.
+/// Devices already used by the driver, e.g. NVMe controllers that are in
+/// use, are not reported.
+pub fn devices(driver: PciDriver) -> Result<Devices, Error> {
+    extern "C" fn enum_cb(ctx: *mut c_void, dev: *mut spdk::spdk_pci_device) -> c_int {
+        let devices = unsafe { &mut *(ctx as *mut Vec<PciDevice>) };
+        devices.push(PciDevice { raw: dev });
+        0
+    }
+
+    let mut devices: Vec<PciDevice> = Vec::new();
+    let rc = unsafe {
+        spdk::spdk_pci_enumerate(
+            driver.to_raw(),
+            Some(enum_cb),
+            &mut devices as *mut Vec<PciDevice> as *mut c_void,
+        )
+    };
+    if rc != 0 {
+        return Err(PciError::EnumerateError(rc))?;
+    }
+    Ok(Devices {
+        devices: devices.into_iter(),
+    })
+}
+
+/// Exclusive claim of a device address between SPDK processes, see `claim`.
+/// Released when dropped.
+pub struct Claim {
+    addr: PciAddr,
+    fd: c_int,
+}
+
+impl Claim {
+    pub fn addr(&self) -> PciAddr {
+        self.addr
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        // Closing the lock file releases the lock taken on it.
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// spdk_pci_device_claim()
+pub fn claim(addr: PciAddr) -> Result<Claim, Error> {
+    let raw = addr.to_raw();
+    let fd = unsafe { spdk::spdk_pci_device_claim(&raw) };
+    if fd < 0 {
+        return Err(PciError::ClaimError(addr, fd))?;
+    }
+    Ok(Claim { addr, fd })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use hamcrest2::prelude::*;
+
+    #[test]
+    fn addresses_are_parsed() {
+        let addr = PciAddr::new(0x10, 0x5e, 0x1f, 7);
+
+        assert_that!(
+            "0010:5e:1f.7".parse::<PciAddr>().unwrap(),
+            is(equal_to(addr))
+        );
+        assert_that!("10.5e.1f.7".parse::<PciAddr>().unwrap(), is(equal_to(addr)));
+        assert_that!(
+            "0010:5e:1f".parse::<PciAddr>().unwrap(),
+            is(equal_to(PciAddr::new(0x10, 0x5e, 0x1f, 0)))
+        );
+        assert_that!(
+            "5e:1f.7".parse::<PciAddr>().unwrap(),
+            is(equal_to(PciAddr::new(0, 0x5e, 0x1f, 7)))
+        );
+        assert_that!(
+            "5e.1f.7".parse::<PciAddr>().unwrap(),
+            is(equal_to(PciAddr::new(0, 0x5e, 0x1f, 7)))
+        );
+        assert_that!(
+            "5e:1f".parse::<PciAddr>().unwrap(),
+            is(equal_to(PciAddr::new(0, 0x5e, 0x1f, 0)))
+        );
+        assert_that!(
+            "5e.1f".parse::<PciAddr>().unwrap(),
+            is(equal_to(PciAddr::new(0, 0x5e, 0x1f, 0)))
+        );
+    }
+
+    #[test]
+    fn invalid_addresses_are_rejected() {
+        assert_that!("".parse::<PciAddr>(), is(err()));
+        assert_that!("pci".parse::<PciAddr>(), is(err()));
+        assert_that!("0000:100:00.0".parse::<PciAddr>(), is(err()));
+        assert_that!("0000:00:20.0".parse::<PciAddr>(), is(err()));
+        assert_that!("0000:00:00.8".parse::<PciAddr>(), is(err()));
+        assert_that!("00\0:00.0".parse::<PciAddr>(), is(err()));
+    }
+
+    #[test]
+    fn addresses_round_trip_and_sort() {
+        let addr = PciAddr::new(0, 0x81, 0, 1);
+        let formatted = addr.to_string();
+
+        assert_that!(formatted.as_str(), is(equal_to("0000:81:00.1")));
+        assert_that!(formatted.parse::<PciAddr>().unwrap(), is(equal_to(addr)));
+
+        let mut addrs = vec![
+            PciAddr::new(1, 0, 0, 0),
+            PciAddr::new(0, 0x81, 0, 1),
+            PciAddr::new(0, 0x81, 0, 0),
+            PciAddr::new(0, 0x05, 0x1f, 0),
+        ];
+        addrs.sort();
+        assert_that!(
+            addrs,
+            is(equal_to(vec![
+                PciAddr::new(0, 0x05, 0x1f, 0),
+                PciAddr::new(0, 0x81, 0, 0),
+                PciAddr::new(0, 0x81, 0, 1),
+                PciAddr::new(1, 0, 0, 0),
+            ]))
+        );
+    }
+}
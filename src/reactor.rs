@@ -0,0 +1,176 @@
+//! Reactor statistics: how much time each reactor spends doing work versus
+//! spinning idle.
+
+use crate::env;
+use crate::io_channel::{poller_register_timed, PollerHandle};
+use crate::time::Ticks;
+use spdk;
+
+use std::cell::RefCell;
+use std::time::Duration;
+
+use failure::Error;
+
+#[derive(Debug, Fail)]
+pub enum ReactorError {
+    #[fail(display = "Could not get the stats of reactor {}: {}", _0, _1)]
+    StatsError(u32, i32),
+}
+
+/// Tick counters of a reactor, see `tsc_stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TscStats {
+    pub busy_tsc: u64,
+    pub idle_tsc: u64,
+    pub unknown_tsc: u64,
+}
+
+impl TscStats {
+    /// Time spent running pollers or events that did some work.
+    pub fn busy(&self) -> Duration {
+        Ticks(self.busy_tsc).to_duration()
+    }
+
+    /// Time spent running pollers or events that found nothing to do.
+    pub fn idle(&self) -> Duration {
+        Ticks(self.idle_tsc).to_duration()
+    }
+
+    /// Time that could not be attributed to either.
+    pub fn unknown(&self) -> Duration {
+        Ticks(self.unknown_tsc).to_duration()
+    }
+
+    /// Share of busy ticks, in percent, between `earlier` and `self`.
+    pub fn utilization_since(&self, earlier: &TscStats) -> f64 {
+        let busy = self.busy_tsc.saturating_sub(earlier.busy_tsc);
+        let idle = self.idle_tsc.saturating_sub(earlier.idle_tsc);
+        if busy + idle == 0 {
+            0.0
+        } else {
+            busy as f64 * 100.0 / (busy + idle) as f64
+        }
+    }
+}
+
+/// spdk_reactor_get_tsc_stats()
+pub fn tsc_stats(core: u32) -> Result<TscStats, Error> {
+    let mut stats: spdk::spdk_reactor_tsc_stats = Default::default();
+    let rc = unsafe { spdk::spdk_reactor_get_tsc_stats(&mut stats, core) };
+    if rc != 0 {
+        return Err(ReactorError::StatsError(core, rc))?;
+    }
+    Ok(TscStats {
+        busy_tsc: stats.busy_tsc,
+        idle_tsc: stats.idle_tsc,
+        unknown_tsc: stats.unknown_tsc,
+    })
+}
+
+/// Stats of every reactor, keyed by core.
+pub fn all_tsc_stats() -> Result<Vec<(u32, TscStats)>, Error> {
+    env::cores()
+        .map(|core| tsc_stats(core).map(|stats| (core, stats)))
+        .collect()
+}
+
+/// spdk_reactor_enable_context_switch_monitor()
+pub fn enable_context_switch_monitor(enabled: bool) {
+    unsafe { spdk::spdk_reactor_enable_context_switch_monitor(enabled) }
+}
+
+/// spdk_reactor_context_switch_monitor_enabled()
+pub fn context_switch_monitor_enabled() -> bool {
+    unsafe { spdk::spdk_reactor_context_switch_monitor_enabled() }
+}
+
+/// Utilization of a reactor over one sampling interval.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CoreUtilization {
+    pub core: u32,
+    pub busy: Duration,
+    pub idle: Duration,
+    /// Busy share of the interval, in percent.
+    pub percent: f64,
+}
+
+/// Computes per-core utilization between consecutive calls to `sample`.
+pub struct UtilizationSampler {
+    last: Vec<(u32, TscStats)>,
+}
+
+impl UtilizationSampler {
+    /// Takes the baseline the first sample is measured against.
+    pub fn new() -> Result<UtilizationSampler, Error> {
+        Ok(UtilizationSampler {
+            last: all_tsc_stats()?,
+        })
+    }
+
+    pub fn sample(&mut self) -> Result<Vec<CoreUtilization>, Error> {
+        let current = all_tsc_stats()?;
+        let utilization = current
+            .iter()
+            .map(|(core, stats)| {
+                let earlier = self
+                    .last
+                    .iter()
+                    .find(|(last_core, _)| last_core == core)
+                    .map(|(_, last)| *last)
+                    .unwrap_or_default();
+                CoreUtilization {
+                    core: *core,
+                    busy: Ticks(stats.busy_tsc.saturating_sub(earlier.busy_tsc)).to_duration(),
+                    idle: Ticks(stats.idle_tsc.saturating_sub(earlier.idle_tsc)).to_duration(),
+                    percent: stats.utilization_since(&earlier),
+                }
+            })
+            .collect();
+        self.last = current;
+        Ok(utilization)
+    }
+}
+
+/// Calls `f` with the utilization of every reactor once per `period`.
+/// The sampling runs from a poller on the current SPDK thread and stops when
+/// the returned handle is dropped.
+pub fn monitor_utilization<F>(period: Duration, f: F) -> Result<PollerHandle, Error>
+where
+    F: Fn(&[CoreUtilization]) + 'static,
+{
+    let sampler = RefCell::new(UtilizationSampler::new()?);
+    Ok(poller_register_timed(
+        move || match sampler.borrow_mut().sample() {
+            Ok(utilization) => {
+                f(&utilization);
+                true
+            }
+            Err(_) => false,
+        },
+        period,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use hamcrest2::prelude::*;
+
+    #[test]
+    fn utilization_is_computed_from_deltas() {
+        let earlier = TscStats {
+            busy_tsc: 100,
+            idle_tsc: 100,
+            unknown_tsc: 0,
+        };
+        let later = TscStats {
+            busy_tsc: 175,
+            idle_tsc: 125,
+            unknown_tsc: 10,
+        };
+
+        assert_that!(later.utilization_since(&earlier), is(equal_to(75.0)));
+        assert_that!(earlier.utilization_since(&earlier), is(equal_to(0.0)));
+    }
+}
@@ -0,0 +1,218 @@
+//! A bdev handle that can be used from any thread, including threads that
+//! are not managed by SPDK (tokio workers, plain std threads...).
+//!
+//! The descriptor and the I/O channel stay on the SPDK thread that opened
+//! them. Every request is shipped to that thread with spdk_thread_send_msg(),
+//! runs on its executor and sends its result back through a oneshot channel.
+//! Data moves along with the request as an owned `Vec<u8>` and is copied in
+//! and out of a DMA buffer on the owning thread.
+
+use crate::bdev::{self, SpdkBdevDesc};
+use crate::env;
+use crate::executor;
+use crate::thread::{self, SpdkIoChannel, SpdkThread};
+
+use std::future::Future;
+use std::ptr;
+use std::sync::Arc;
+
+use failure::Error;
+use futures::channel::oneshot;
+
+#[derive(Debug, Fail)]
+pub enum RemoteBdevError {
+    #[fail(display = "A remote bdev must be opened from an SPDK thread")]
+    NotOnSpdkThread(),
+
+    #[fail(display = "Request to bdev {} was dropped by its owning thread", _0)]
+    Cancelled(String),
+}
+
+struct Inner {
+    name: String,
+    desc: SpdkBdevDesc,
+    channel: SpdkIoChannel,
+    block_size: u32,
+    buf_align: usize,
+    owner: SpdkThread,
+}
+
+// The descriptor and the channel are only ever touched on `owner`, see
+// `RemoteBdev::submit` and the `Drop` impl below.
+unsafe impl Send for Inner {}
+unsafe impl Sync for Inner {}
+
+impl Inner {
+    fn on_owner(&self) -> bool {
+        SpdkThread::current().map(|current| current.to_raw()) == Some(self.owner.to_raw())
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        let resources = Resources {
+            desc: self.desc.clone(),
+            channel: SpdkIoChannel::from_raw(self.channel.to_raw()),
+        };
+        if self.on_owner() {
+            resources.release();
+        } else {
+            thread::send_msg(&self.owner, move || resources.release());
+        }
+    }
+}
+
+struct Resources {
+    desc: SpdkBdevDesc,
+    channel: SpdkIoChannel,
+}
+
+unsafe impl Send for Resources {}
+
+impl Resources {
+    fn release(self) {
+        thread::put_io_channel(self.channel);
+        bdev::close(self.desc);
+    }
+}
+
+/// `Send + Sync` handle to a bdev opened on an SPDK thread. Clones share the
+/// same descriptor, which is closed when the last clone goes away.
+#[derive(Clone)]
+pub struct RemoteBdev {
+    inner: Arc<Inner>,
+}
+
+impl RemoteBdev {
+    /// Opens `name` and gets an I/O channel for it. Must be called on an SPDK
+    /// thread with an executor installed, which becomes the owner of the handle.
+    pub fn open(name: &str, write: bool) -> Result<RemoteBdev, Error> {
+        let owner = SpdkThread::current().ok_or(RemoteBdevError::NotOnSpdkThread())?;
+        let bdev = bdev::get_by_name(name)?;
+        let mut desc = SpdkBdevDesc::new();
+        bdev::open(bdev.clone(), write, &mut desc)?;
+        let channel = match bdev::get_io_channel(desc.clone()) {
+            Ok(channel) => channel,
+            Err(e) => {
+                bdev::close(desc);
+                return Err(e);
+            }
+        };
+        Ok(RemoteBdev {
+            inner: Arc::new(Inner {
+                name: name.to_string(),
+                desc,
+                channel,
+                block_size: bdev::get_block_size(bdev.clone()),
+                buf_align: bdev::get_buf_align(bdev),
+                owner,
+            }),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.inner.name
+    }
+
+    pub fn block_size(&self) -> u32 {
+        self.inner.block_size
+    }
+
+    /// The SPDK thread the I/O is submitted from.
+    pub fn owner(&self) -> &SpdkThread {
+        &self.inner.owner
+    }
+
+    /// Reads `nbytes` starting at `offset`.
+    pub fn read(
+        &self,
+        offset: u64,
+        nbytes: u64,
+    ) -> impl Future<Output = Result<Vec<u8>, Error>> + Send {
+        self.submit(move |inner| async move {
+            // DMA buffers cannot be empty.
+            if nbytes == 0 {
+                return Ok(Vec::new());
+            }
+            let mut buf = env::dma_zmalloc(nbytes as usize, inner.buf_align);
+            let res =
+                bdev::read(inner.desc.clone(), &inner.channel, &mut buf, offset, nbytes).await;
+            let res = res.map(|()| {
+                let mut data = vec![0u8; nbytes as usize];
+                unsafe {
+                    ptr::copy_nonoverlapping(
+                        buf.to_raw() as *const u8,
+                        data.as_mut_ptr(),
+                        data.len(),
+                    )
+                };
+                data
+            });
+            env::dma_free(buf);
+            res
+        })
+    }
+
+    /// Writes `data` starting at `offset`.
+    pub fn write(
+        &self,
+        offset: u64,
+        data: Vec<u8>,
+    ) -> impl Future<Output = Result<(), Error>> + Send {
+        self.submit(move |inner| async move {
+            if data.is_empty() {
+                return Ok(());
+            }
+            let buf = env::dma_zmalloc(data.len(), inner.buf_align);
+            unsafe { ptr::copy_nonoverlapping(data.as_ptr(), buf.to_raw() as *mut u8, data.len()) };
+            let res = bdev::write(
+                inner.desc.clone(),
+                &inner.channel,
+                &buf,
+                offset,
+                data.len() as u64,
+            )
+            .await;
+            env::dma_free(buf);
+            res
+        })
+    }
+
+    /// Flushes `nbytes` starting at `offset`.
+    pub fn flush(
+        &self,
+        offset: u64,
+        nbytes: u64,
+    ) -> impl Future<Output = Result<(), Error>> + Send {
+        self.submit(move |inner| async move {
+            bdev::flush(inner.desc.clone(), &inner.channel, offset, nbytes).await
+        })
+    }
+
+    /// Runs the future built by `f` on the owning thread once the returned
+    /// future is first polled, and resolves with its output. If it panics,
+    /// the request fails with `RemoteBdevError::Cancelled`.
+    fn submit<F, Fut, T>(&self, f: F) -> impl Future<Output = Result<T, Error>> + Send
+    where
+        F: FnOnce(Arc<Inner>) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<T, Error>> + 'static,
+        T: Send + 'static,
+    {
+        let inner = self.inner.clone();
+        async move {
+            let (sender, receiver) = oneshot::channel();
+            let owner = inner.owner.clone();
+            let name = inner.name.clone();
+            thread::send_msg(&owner, move || {
+                executor::spawn_remote(async move {
+                    let res = f(inner).await;
+                    let _ = sender.send(res);
+                })
+            });
+            match receiver.await {
+                Ok(res) => res,
+                Err(_) => Err(RemoteBdevError::Cancelled(name))?,
+            }
+        }
+    }
+}
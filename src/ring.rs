@@ -0,0 +1,331 @@
+//! Typed wrapper around `spdk_ring`, a lockless queue that can be used to
+//! pass ownership of boxed values between cores.
+//!
+//! Items are moved as `Box<T>`: only the pointer goes through the ring, the
+//! value itself is never copied.
+
+use crate::io_channel::{poller_register, PollerHandle};
+use spdk;
+
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+
+use failure::Error;
+use futures::stream::Stream;
+
+#[derive(Debug, Fail)]
+pub enum RingError {
+    #[fail(display = "Could not create a ring of size {}", _0)]
+    CreateError(usize),
+}
+
+/// spdk_ring_type
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RingType {
+    /// Single producer, single consumer.
+    SingleProducer,
+    /// Multiple producers, single consumer.
+    MultiProducer,
+}
+
+impl RingType {
+    fn to_raw(self) -> spdk::spdk_ring_type {
+        match self {
+            RingType::SingleProducer => spdk::spdk_ring_type_SPDK_RING_TYPE_SP_SC,
+            RingType::MultiProducer => spdk::spdk_ring_type_SPDK_RING_TYPE_MP_SC,
+        }
+    }
+}
+
+struct Ring<T: Send> {
+    raw: *mut spdk::spdk_ring,
+    _marker: PhantomData<Box<T>>,
+}
+
+// The ring only ever holds `Box<T>` with `T: Send`, and the producer/consumer
+// halves below make sure the ring is used according to its type.
+unsafe impl<T: Send> Send for Ring<T> {}
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+impl<T: Send> Ring<T> {
+    /// spdk_ring_create()
+    fn new(ring_type: RingType, count: usize, socket_id: i32) -> Result<Ring<T>, Error> {
+        let raw = unsafe { spdk::spdk_ring_create(ring_type.to_raw(), count, socket_id) };
+        if raw.is_null() {
+            return Err(RingError::CreateError(count))?;
+        }
+        Ok(Ring {
+            raw,
+            _marker: PhantomData,
+        })
+    }
+
+    /// spdk_ring_count()
+    fn count(&self) -> usize {
+        unsafe { spdk::spdk_ring_count(self.raw) }
+    }
+
+    /// spdk_ring_enqueue()
+    ///
+    /// Returns the items that did not fit into the ring.
+    fn enqueue(&self, items: Vec<Box<T>>) -> Vec<Box<T>> {
+        let mut ptrs: Vec<*mut c_void> = items
+            .into_iter()
+            .map(|item| Box::into_raw(item) as *mut c_void)
+            .collect();
+        let enqueued = unsafe { spdk::spdk_ring_enqueue(self.raw, ptrs.as_mut_ptr(), ptrs.len()) };
+        ptrs.drain(..enqueued);
+        ptrs.into_iter()
+            .map(|ptr| unsafe { Box::from_raw(ptr as *mut T) })
+            .collect()
+    }
+
+    /// spdk_ring_dequeue()
+    fn dequeue(&self, max: usize) -> Vec<Box<T>> {
+        let mut ptrs: Vec<*mut c_void> = Vec::with_capacity(max);
+        unsafe {
+            let dequeued = spdk::spdk_ring_dequeue(self.raw, ptrs.as_mut_ptr(), max);
+            ptrs.set_len(dequeued);
+        }
+        ptrs.into_iter()
+            .map(|ptr| unsafe { Box::from_raw(ptr as *mut T) })
+            .collect()
+    }
+}
+
+impl<T: Send> Drop for Ring<T> {
+    fn drop(&mut self) {
+        // Free whatever is still queued before releasing the ring itself.
+        while !self.dequeue(self.count().max(1)).is_empty() {}
+        unsafe { spdk::spdk_ring_free(self.raw) }
+    }
+}
+
+/// Creates a single producer, single consumer ring with room for `count` items.
+pub fn channel<T: Send>(count: usize) -> Result<(Sender<T>, Receiver<T>), Error> {
+    let ring = Arc::new(Ring::new(
+        RingType::SingleProducer,
+        count,
+        spdk::SPDK_ENV_SOCKET_ID_ANY,
+    )?);
+    Ok((Sender { ring: ring.clone() }, Receiver { ring }))
+}
+
+/// Creates a multiple producers, single consumer ring with room for `count` items.
+pub fn mp_channel<T: Send>(count: usize) -> Result<(MpSender<T>, Receiver<T>), Error> {
+    let ring = Arc::new(Ring::new(
+        RingType::MultiProducer,
+        count,
+        spdk::SPDK_ENV_SOCKET_ID_ANY,
+    )?);
+    Ok((MpSender { ring: ring.clone() }, Receiver { ring }))
+}
+
+/// Producing half of a single producer ring. It can be moved to another
+/// core, but not shared or cloned.
+pub struct Sender<T: Send> {
+    ring: Arc<Ring<T>>,
+}
+
+impl<T: Send> Sender<T> {
+    /// Enqueues a single item, giving it back if the ring is full.
+    pub fn send(&mut self, item: Box<T>) -> Result<(), Box<T>> {
+        match self.ring.enqueue(vec![item]).pop() {
+            Some(item) => Err(item),
+            None => Ok(()),
+        }
+    }
+
+    /// Enqueues all items, returning the ones that did not fit.
+    pub fn send_bulk(&mut self, items: Vec<Box<T>>) -> Vec<Box<T>> {
+        self.ring.enqueue(items)
+    }
+}
+
+/// Producing half of a multiple producers ring, it can be cloned and
+/// shared between cores.
+pub struct MpSender<T: Send> {
+    ring: Arc<Ring<T>>,
+}
+
+impl<T: Send> Clone for MpSender<T> {
+    fn clone(&self) -> MpSender<T> {
+        MpSender {
+            ring: self.ring.clone(),
+        }
+    }
+}
+
+impl<T: Send> MpSender<T> {
+    /// Enqueues a single item, giving it back if the ring is full.
+    pub fn send(&self, item: Box<T>) -> Result<(), Box<T>> {
+        match self.ring.enqueue(vec![item]).pop() {
+            Some(item) => Err(item),
+            None => Ok(()),
+        }
+    }
+
+    /// Enqueues all items, returning the ones that did not fit.
+    pub fn send_bulk(&self, items: Vec<Box<T>>) -> Vec<Box<T>> {
+        self.ring.enqueue(items)
+    }
+}
+
+/// Consuming half of a ring.
+pub struct Receiver<T: Send> {
+    ring: Arc<Ring<T>>,
+}
+
+impl<T: Send> Receiver<T> {
+    /// Dequeues a single item, if any.
+    pub fn try_recv(&mut self) -> Option<Box<T>> {
+        self.ring.dequeue(1).pop()
+    }
+
+    /// Dequeues up to `max` items.
+    pub fn recv_bulk(&mut self, max: usize) -> Vec<Box<T>> {
+        self.ring.dequeue(max)
+    }
+
+    /// Number of items currently queued.
+    pub fn len(&self) -> usize {
+        self.ring.count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns true once every sender has been dropped.
+    pub fn is_disconnected(&self) -> bool {
+        Arc::strong_count(&self.ring) == 1
+    }
+
+    /// Turns the receiver into a `Stream` that is woken by a poller registered
+    /// on the current SPDK thread. The stream ends once every sender has been
+    /// dropped and the ring is drained.
+    pub fn into_stream(self) -> RingStream<T>
+    where
+        T: 'static,
+    {
+        let waker: Rc<RefCell<Option<Waker>>> = Rc::new(RefCell::new(None));
+        let poller_ring = self.ring.clone();
+        let poller_waker = waker.clone();
+        let poller = poller_register(move || {
+            let ready = poller_ring.count() > 0 || Arc::strong_count(&poller_ring) <= 2;
+            if !ready {
+                return false;
+            }
+            match poller_waker.borrow_mut().take() {
+                Some(waker) => {
+                    waker.wake();
+                    true
+                }
+                None => false,
+            }
+        });
+        RingStream {
+            receiver: self,
+            waker,
+            _poller: poller,
+        }
+    }
+}
+
+/// Asynchronous receiving half of a ring, see `Receiver::into_stream`.
+pub struct RingStream<T: Send> {
+    receiver: Receiver<T>,
+    waker: Rc<RefCell<Option<Waker>>>,
+    _poller: PollerHandle,
+}
+
+impl<T: Send> RingStream<T> {
+    fn is_disconnected(&self) -> bool {
+        // The poller keeps its own reference to the ring.
+        Arc::strong_count(&self.receiver.ring) <= 2
+    }
+}
+
+impl<T: Send> Stream for RingStream<T> {
+    type Item = Box<T>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Box<T>>> {
+        let this = Pin::get_mut(self);
+        if let Some(item) = this.receiver.try_recv() {
+            return Poll::Ready(Some(item));
+        }
+        if this.is_disconnected() {
+            // A sender might have enqueued right before going away.
+            return Poll::Ready(this.receiver.try_recv());
+        }
+        *this.waker.borrow_mut() = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use hamcrest2::prelude::*;
+
+    #[test]
+    fn items_are_received_in_order() {
+        let (mut sender, mut receiver) = channel::<u32>(16).unwrap();
+        assert_that!(receiver.try_recv(), is(equal_to(None)));
+
+        assert_that!(sender.send(Box::new(1)), is(ok()));
+        assert_that!(
+            sender.send_bulk(vec![Box::new(2), Box::new(3)]).len(),
+            is(equal_to(0))
+        );
+        assert_that!(receiver.len(), is(equal_to(3)));
+
+        assert_that!(receiver.try_recv(), is(equal_to(Some(Box::new(1)))));
+        assert_that!(
+            receiver.recv_bulk(8),
+            is(equal_to(vec![Box::new(2), Box::new(3)]))
+        );
+        assert_that!(receiver.is_empty(), is(equal_to(true)));
+    }
+
+    #[test]
+    fn full_ring_hands_items_back() {
+        let (mut sender, mut receiver) = channel::<u32>(4).unwrap();
+
+        let mut sent = 0;
+        let rejected = loop {
+            match sender.send(Box::new(sent)) {
+                Ok(()) => sent += 1,
+                Err(item) => break item,
+            }
+        };
+        assert_that!(*rejected, is(equal_to(sent)));
+        assert_that!(receiver.len(), is(equal_to(sent as usize)));
+
+        let items: Vec<u32> = receiver
+            .recv_bulk(8)
+            .into_iter()
+            .map(|item| *item)
+            .collect();
+        assert_that!(items, is(equal_to((0..sent).collect::<Vec<_>>())));
+        assert_that!(sender.send(rejected), is(ok()));
+    }
+
+    #[test]
+    fn receiver_sees_senders_go_away() {
+        let (sender, receiver) = mp_channel::<u32>(4).unwrap();
+        let other = sender.clone();
+        assert_that!(receiver.is_disconnected(), is(equal_to(false)));
+
+        drop(sender);
+        assert_that!(receiver.is_disconnected(), is(equal_to(false)));
+        drop(other);
+        assert_that!(receiver.is_disconnected(), is(equal_to(true)));
+    }
+}
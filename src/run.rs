@@ -1,22 +1,29 @@
-use crate::event;
+use crate::executor;
+use crate::log::{self, LogLevel};
 use std::future::Future as StdFuture;
-use tokio::prelude::*;
-use tokio::runtime::Runtime;
 
-async fn map_ok<T: StdFuture>(future: T) -> Result<(), ()> {
-    let _ = await!(future);
-    Ok(())
-}
+use failure::Error;
 
+/// Runs `future` to completion on the current reactor and stops the app.
+///
+/// Must be called from an SPDK thread, typically from the closure passed to
+/// `SpdkAppOpts::start`. The future is polled by the crate's own executor on
+/// that thread, so it can call into bdev and other thread-bound SPDK APIs and
+/// does not need to be `Send`. If it returns an error or panics, the app is
+/// stopped with a failure status and the error is logged.
 pub fn run_spdk<F>(future: F)
 where
-    F: StdFuture<Output = ()> + Send + 'static,
+    F: StdFuture<Output = Result<(), Error>> + 'static,
 {
-    use tokio_async_await::compat::backward;
-    let future = backward::Compat::new(map_ok(future));
-
-    let mut rt = Runtime::new().unwrap();
-    rt.block_on(future);
-    rt.shutdown_now().wait().unwrap();
-    event::app_stop(true);
+    executor::run_main(future, |res| {
+        if let Err(e) = res {
+            log::log(
+                LogLevel::Error,
+                file!(),
+                line!(),
+                module_path!(),
+                &format!("Application failed: {}", e),
+            );
+        }
+    });
 }
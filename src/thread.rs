@@ -1,6 +1,5 @@
 use spdk;
 use std::ffi::{c_void, CStr, CString};
-use std::ptr;
 
 use failure::Error;
 
@@ -8,6 +7,9 @@ use failure::Error;
 pub enum ThreadError {
     #[fail(display = "Failed to allocate thread!")]
     ThreadAllocationError(),
+
+    #[fail(display = "Failed to initialize the thread library: {}", _0)]
+    LibInitError(i32),
 }
 
 #[derive(Clone)]
@@ -25,36 +27,148 @@ impl SpdkIoChannel {
     }
 }
 
+#[derive(Clone)]
 pub struct SpdkThread {
     raw: *mut spdk::spdk_thread,
 }
 
+// Only thread-safe functions (such as spdk_thread_send_msg) may be called on
+// a thread from outside of it; the rest is only used by the thread itself.
+unsafe impl Send for SpdkThread {}
+unsafe impl Sync for SpdkThread {}
+
 impl SpdkThread {
     pub fn from_raw(raw: *mut spdk::spdk_thread) -> SpdkThread {
         unsafe { SpdkThread { raw } }
     }
+
+    pub fn to_raw(&self) -> *mut spdk::spdk_thread {
+        self.raw
+    }
+
+    /// spdk_thread_create()
+    pub fn create<S>(name: S) -> Result<SpdkThread, Error>
+    where
+        S: Into<String> + Clone,
+    {
+        let name_cstring = CString::new(name.clone().into()).expect("Couldn't create a string");
+
+        let thread_struct = unsafe { spdk::spdk_thread_create(name_cstring.as_ptr()) };
+        if thread_struct.is_null() {
+            return Err(ThreadError::ThreadAllocationError())?;
+        }
+
+        Ok(SpdkThread::from_raw(thread_struct))
+    }
+
+    /// spdk_get_thread()
+    ///
+    /// Returns the SPDK thread the caller is currently running on, if any.
+    pub fn current() -> Option<SpdkThread> {
+        let ptr = unsafe { spdk::spdk_get_thread() };
+        if ptr.is_null() {
+            None
+        } else {
+            Some(SpdkThread::from_raw(ptr))
+        }
+    }
+
+    /// spdk_thread_get_count()
+    pub fn count() -> u32 {
+        unsafe { spdk::spdk_thread_get_count() }
+    }
+
+    /// spdk_thread_get_name()
+    pub fn name(&self) -> String {
+        let c_str = unsafe { CStr::from_ptr(spdk::spdk_thread_get_name(self.raw)) };
+        c_str.to_string_lossy().into_owned()
+    }
+
+    /// spdk_thread_poll()
+    ///
+    /// Runs one iteration of the thread: processes up to `max_msgs` messages
+    /// (0 means the default batch size) and runs the pollers that are due.
+    /// Returns true if any work was done.
+    pub fn poll(&self, max_msgs: u32) -> bool {
+        unsafe { spdk::spdk_thread_poll(self.raw, max_msgs) > 0 }
+    }
+
+    /// spdk_thread_has_active_pollers()
+    pub fn has_active_pollers(&self) -> bool {
+        unsafe { spdk::spdk_thread_has_active_pollers(self.raw) != 0 }
+    }
+
+    /// spdk_thread_next_poller_expiration()
+    ///
+    /// Returns the tick at which the next timed poller expires, or 0 if
+    /// there are no timed pollers.
+    pub fn next_poller_expiration(&self) -> u64 {
+        unsafe { spdk::spdk_thread_next_poller_expiration(self.raw) }
+    }
+
+    /// spdk_thread_exit()
+    ///
+    /// Releases the thread. All pollers and I/O channels must have been
+    /// released beforehand.
+    ///
+    /// # Safety
+    ///
+    /// `SpdkThread` is only a handle: the thread must not be used, nor exited
+    /// again, through any other handle to it once this returns.
+    pub unsafe fn exit(self) {
+        spdk::spdk_thread_exit(self.raw)
+    }
 }
 
-pub fn allocate_thread<S>(name: S) -> Result<SpdkThread, Error>
+/// spdk_thread_send_msg()
+///
+/// Runs `f` on `thread` the next time it is polled. Can be called from any
+/// thread.
+pub fn send_msg<F>(thread: &SpdkThread, f: F)
 where
-    S: Into<String> + Clone,
+    F: FnOnce() + Send + 'static,
 {
-    let name_cstring = CString::new(name.clone().into()).expect("Couldn't create a string");
-
-    let thread_struct = unsafe {
-        spdk::spdk_allocate_thread(None, None, None, ptr::null_mut(), name_cstring.as_ptr())
-    };
-    if thread_struct.is_null() {
-        return Err(ThreadError::ThreadAllocationError())?;
+    extern "C" fn msg_wrapper<F>(closure: *mut c_void)
+    where
+        F: FnOnce(),
+    {
+        let f = unsafe { Box::from_raw(closure as *mut F) };
+        f()
     }
 
-    Ok(SpdkThread::from_raw(thread_struct))
+    let f_pointer = Box::into_raw(Box::new(f));
+    unsafe {
+        spdk::spdk_thread_send_msg(
+            thread.to_raw(),
+            Some(msg_wrapper::<F>),
+            f_pointer as *mut c_void,
+        )
+    }
 }
 
-pub fn free_thread() {
-    unsafe {
-        spdk::spdk_free_thread();
+/// spdk_thread_lib_init()
+///
+/// Must be called once before any thread is created when the threads are
+/// driven by the application (i.e. without `spdk_app_start`).
+pub fn thread_lib_init() -> Result<(), Error> {
+    let rc = unsafe { spdk::spdk_thread_lib_init(None) };
+    if rc != 0 {
+        return Err(ThreadError::LibInitError(rc))?;
     }
+    Ok(())
+}
+
+/// spdk_thread_lib_fini()
+pub fn thread_lib_fini() {
+    unsafe { spdk::spdk_thread_lib_fini() }
+}
+
+/// Creates a new SPDK thread, see `SpdkThread::create`.
+pub fn create_thread<S>(name: S) -> Result<SpdkThread, Error>
+where
+    S: Into<String> + Clone,
+{
+    SpdkThread::create(name)
 }
 
 pub fn put_io_channel(channel: SpdkIoChannel) {
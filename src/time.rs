@@ -0,0 +1,214 @@
+//! Monotonic clock built on the SPDK timestamp counter.
+//!
+//! Reading spdk_get_ticks() is a plain TSC read on x86, which is much cheaper
+//! than going through `std::time::Instant`.
+
+use spdk;
+
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+use std::time::Duration;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// A number of ticks of the timestamp counter.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ticks(pub u64);
+
+impl Ticks {
+    /// spdk_get_ticks_hz()
+    pub fn hz() -> u64 {
+        unsafe { spdk::spdk_get_ticks_hz() }
+    }
+
+    pub fn from_duration(duration: Duration) -> Ticks {
+        Ticks(duration_to_ticks(duration, Ticks::hz()))
+    }
+
+    pub fn to_duration(self) -> Duration {
+        ticks_to_duration(self.0, Ticks::hz())
+    }
+
+    pub fn saturating_sub(self, other: Ticks) -> Ticks {
+        Ticks(self.0.saturating_sub(other.0))
+    }
+}
+
+impl Add for Ticks {
+    type Output = Ticks;
+
+    fn add(self, other: Ticks) -> Ticks {
+        Ticks(self.0 + other.0)
+    }
+}
+
+impl AddAssign for Ticks {
+    fn add_assign(&mut self, other: Ticks) {
+        self.0 += other.0;
+    }
+}
+
+impl Sub for Ticks {
+    type Output = Ticks;
+
+    fn sub(self, other: Ticks) -> Ticks {
+        Ticks(self.0 - other.0)
+    }
+}
+
+impl SubAssign for Ticks {
+    fn sub_assign(&mut self, other: Ticks) {
+        self.0 -= other.0;
+    }
+}
+
+/// A point in time, read with spdk_get_ticks().
+///
+/// Only comparable with instants taken in the same process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TickInstant(u64);
+
+impl TickInstant {
+    /// spdk_get_ticks()
+    pub fn now() -> TickInstant {
+        TickInstant(unsafe { spdk::spdk_get_ticks() })
+    }
+
+    pub fn from_raw(ticks: u64) -> TickInstant {
+        TickInstant(ticks)
+    }
+
+    pub fn to_raw(self) -> u64 {
+        self.0
+    }
+
+    /// Ticks elapsed since `earlier`, zero if `earlier` is later than `self`.
+    pub fn ticks_since(self, earlier: TickInstant) -> Ticks {
+        Ticks(self.0.saturating_sub(earlier.0))
+    }
+
+    /// Time elapsed since `earlier`, zero if `earlier` is later than `self`.
+    pub fn duration_since(self, earlier: TickInstant) -> Duration {
+        self.ticks_since(earlier).to_duration()
+    }
+
+    pub fn elapsed_ticks(self) -> Ticks {
+        TickInstant::now().ticks_since(self)
+    }
+
+    pub fn elapsed(self) -> Duration {
+        self.elapsed_ticks().to_duration()
+    }
+}
+
+impl Add<Ticks> for TickInstant {
+    type Output = TickInstant;
+
+    fn add(self, ticks: Ticks) -> TickInstant {
+        TickInstant(self.0 + ticks.0)
+    }
+}
+
+impl Add<Duration> for TickInstant {
+    type Output = TickInstant;
+
+    fn add(self, duration: Duration) -> TickInstant {
+        self + Ticks::from_duration(duration)
+    }
+}
+
+impl AddAssign<Duration> for TickInstant {
+    fn add_assign(&mut self, duration: Duration) {
+        *self = *self + duration;
+    }
+}
+
+impl Sub<Ticks> for TickInstant {
+    type Output = TickInstant;
+
+    fn sub(self, ticks: Ticks) -> TickInstant {
+        TickInstant(self.0 - ticks.0)
+    }
+}
+
+impl Sub<Duration> for TickInstant {
+    type Output = TickInstant;
+
+    fn sub(self, duration: Duration) -> TickInstant {
+        self - Ticks::from_duration(duration)
+    }
+}
+
+impl SubAssign<Duration> for TickInstant {
+    fn sub_assign(&mut self, duration: Duration) {
+        *self = *self - duration;
+    }
+}
+
+impl Sub for TickInstant {
+    type Output = Duration;
+
+    /// Same as `duration_since`.
+    fn sub(self, earlier: TickInstant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// spdk_delay_us()
+///
+/// Busy waits for `us` microseconds.
+pub fn delay_us(us: u32) {
+    unsafe { spdk::spdk_delay_us(us) }
+}
+
+fn ticks_to_duration(ticks: u64, hz: u64) -> Duration {
+    Duration::new(
+        ticks / hz,
+        ((u128::from(ticks % hz) * NANOS_PER_SEC) / u128::from(hz)) as u32,
+    )
+}
+
+/// Rounds to the nearest tick. A tick can be shorter than a nanosecond, so
+/// converting ticks to a `Duration` and back may still be off by one.
+fn duration_to_ticks(duration: Duration, hz: u64) -> u64 {
+    ((duration.as_nanos() * u128::from(hz) + NANOS_PER_SEC / 2) / NANOS_PER_SEC) as u64
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use hamcrest2::prelude::*;
+
+    #[test]
+    fn ticks_convert_to_and_from_durations() {
+        let hz = 2_500_000_000;
+
+        assert_that!(
+            ticks_to_duration(3 * hz + hz / 4, hz),
+            is(equal_to(Duration::from_millis(3250)))
+        );
+        assert_that!(
+            duration_to_ticks(Duration::from_micros(1), hz),
+            is(equal_to(2500))
+        );
+        let round_trip = duration_to_ticks(ticks_to_duration(123_456_789, hz), hz);
+        assert_that!(
+            (round_trip as i64 - 123_456_789).abs() <= 1,
+            is(equal_to(true))
+        );
+        assert_that!(
+            duration_to_ticks(ticks_to_duration(123_456_789, 1_000_000_000), 1_000_000_000),
+            is(equal_to(123_456_789))
+        );
+    }
+
+    #[test]
+    fn instants_do_not_go_backwards() {
+        let earlier = TickInstant::from_raw(100);
+        let later = earlier + Ticks(50);
+
+        assert_that!(later.ticks_since(earlier), is(equal_to(Ticks(50))));
+        assert_that!(earlier.ticks_since(later), is(equal_to(Ticks(0))));
+        assert_that!(later - Ticks(50), is(equal_to(earlier)));
+    }
+}
@@ -0,0 +1,37 @@
+use spdk;
+
+use std::ffi::{c_void, CStr};
+use std::os::raw::c_char;
+use std::ptr;
+
+use failure::Error;
+
+#[derive(Debug, Fail)]
+pub enum UtilError {
+    #[fail(display = "Could not open a memory stream")]
+    MemstreamError(),
+}
+
+/// Runs `f` with a `FILE` backed by memory, see open_memstream(3), and returns
+/// what it wrote.
+pub(crate) fn with_memstream<F>(f: F) -> Result<String, Error>
+where
+    F: FnOnce(*mut spdk::FILE),
+{
+    let mut buf: *mut c_char = ptr::null_mut();
+    let mut size: usize = 0;
+    let fp = unsafe { spdk::open_memstream(&mut buf, &mut size) };
+    if fp.is_null() {
+        return Err(UtilError::MemstreamError())?;
+    }
+
+    f(fp);
+
+    unsafe {
+        // Flushes the stream and updates `buf` and `size`.
+        spdk::fclose(fp);
+        let text = CStr::from_ptr(buf).to_string_lossy().into_owned();
+        spdk::free(buf as *mut c_void);
+        Ok(text)
+    }
+}